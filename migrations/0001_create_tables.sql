@@ -0,0 +1,28 @@
+-- The tables as they were before migrations were tracked, so existing databases are left alone.
+CREATE TABLE IF NOT EXISTS users (
+    user_id integer PRIMARY KEY,
+    name text NOT NULL,
+    avatar_s3 text NOT NULL,
+    avatar_anilist text NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS anime (
+    anime_id integer PRIMARY KEY,
+    description text NOT NULL,
+    cover_s3 text NOT NULL,
+    cover_anilist text NOT NULL,
+    average smallint,
+    native text,
+    romaji text,
+    english text
+);
+
+CREATE TABLE IF NOT EXISTS lists (
+    user_id integer NOT NULL REFERENCES users (user_id),
+    anime_id integer NOT NULL REFERENCES anime (anime_id),
+    user_title text,
+    start_day date,
+    end_day date,
+    score smallint,
+    PRIMARY KEY (user_id, anime_id)
+);
@@ -0,0 +1,7 @@
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS format text;
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS episodes integer;
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS duration integer;
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS mal_id integer;
+
+ALTER TABLE lists ADD COLUMN IF NOT EXISTS status text;
+ALTER TABLE lists ADD COLUMN IF NOT EXISTS progress integer;
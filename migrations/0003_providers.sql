@@ -0,0 +1,26 @@
+ALTER TABLE users ADD COLUMN IF NOT EXISTS provider text NOT NULL DEFAULT 'anilist';
+ALTER TABLE users ADD COLUMN IF NOT EXISTS external_id integer;
+
+-- Names used to be stored with their provider's prefix, such as kitsu:<name>. The column default
+-- above makes every existing user an AniList user, so the prefix is what tells the rest apart.
+-- AniList names can't contain a colon.
+UPDATE users SET provider = split_part(name, ':', 1)
+    WHERE provider = 'anilist' AND name LIKE '%:%' AND split_part(name, ':', 1) IN ('kitsu', 'local');
+UPDATE users SET name = substr(name, length(provider) + 2)
+    WHERE provider <> 'anilist' AND name LIKE provider || ':%';
+
+-- AniList users have always been stored under their AniList id.
+UPDATE users SET external_id = user_id WHERE provider = 'anilist' AND external_id IS NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_provider_external_id ON users (provider, external_id);
+CREATE UNIQUE INDEX IF NOT EXISTS users_provider_lower_name ON users (provider, lower(name));
+
+CREATE TABLE IF NOT EXISTS user_aliases (
+    provider text NOT NULL,
+    name text NOT NULL,
+    user_id integer NOT NULL REFERENCES users (user_id),
+    renamed_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (provider, name)
+);
+
+CREATE INDEX IF NOT EXISTS user_aliases_provider_lower_name ON user_aliases (provider, lower(name));
@@ -0,0 +1,5 @@
+-- When each user's list was last synced successfully, and how their latest sync went.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_synced_at timestamptz;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_sync_status text;
+-- In milliseconds.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_sync_duration integer;
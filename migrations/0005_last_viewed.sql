@@ -0,0 +1,4 @@
+-- When each user's list was last looked at, so background refreshes can favour active profiles.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_viewed_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS users_last_synced_at ON users (last_synced_at);
@@ -0,0 +1,30 @@
+-- One row per sync of a user's list, whether or not it succeeded.
+CREATE TABLE IF NOT EXISTS sync_runs (
+    run_id serial PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (user_id),
+    started_at timestamptz NOT NULL DEFAULT now(),
+    finished_at timestamptz,
+    added integer NOT NULL DEFAULT 0,
+    updated integer NOT NULL DEFAULT 0,
+    removed integer NOT NULL DEFAULT 0,
+    images_uploaded integer NOT NULL DEFAULT 0,
+    error text
+);
+
+CREATE INDEX IF NOT EXISTS sync_runs_user_id ON sync_runs (user_id, started_at DESC);
+
+-- What each sync changed. Added and removed entries have one row each, while an updated entry has
+-- a row per changed field.
+CREATE TABLE IF NOT EXISTS list_changes (
+    change_id serial PRIMARY KEY,
+    run_id integer NOT NULL REFERENCES sync_runs (run_id),
+    user_id integer NOT NULL REFERENCES users (user_id),
+    anime_id integer NOT NULL REFERENCES anime (anime_id),
+    changed_at timestamptz NOT NULL,
+    change text NOT NULL,
+    field text,
+    old_value text,
+    new_value text
+);
+
+CREATE INDEX IF NOT EXISTS list_changes_user_id ON list_changes (user_id, changed_at DESC);
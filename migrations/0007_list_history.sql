@@ -0,0 +1,62 @@
+-- Every version of each list entry, valid from `valid_from` until `valid_to`, or still current when
+-- `valid_to` is null. History begins when this migration runs, so earlier dates have none.
+CREATE TABLE IF NOT EXISTS list_history (
+    history_id serial PRIMARY KEY,
+    user_id integer NOT NULL REFERENCES users (user_id),
+    anime_id integer NOT NULL REFERENCES anime (anime_id),
+    user_title text,
+    start_day date,
+    end_day date,
+    score smallint,
+    status text,
+    progress integer,
+    valid_from timestamptz NOT NULL,
+    valid_to timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS list_history_user_id ON list_history (user_id, anime_id, valid_from);
+CREATE UNIQUE INDEX IF NOT EXISTS list_history_current ON list_history (user_id, anime_id)
+    WHERE valid_to IS NULL;
+
+INSERT INTO list_history
+    (user_id, anime_id, user_title, start_day, end_day, score, status, progress, valid_from)
+SELECT l.user_id, l.anime_id, l.user_title, l.start_day, l.end_day, l.score, l.status, l.progress,
+    now()
+FROM lists as l
+WHERE NOT EXISTS (
+    SELECT 1 FROM list_history as h
+    WHERE h.user_id = l.user_id AND h.anime_id = l.anime_id AND h.valid_to IS NULL
+);
+
+-- Kept up to date by a trigger so syncs, imports and anything else writing to `lists` all record
+-- history. Upserts rewrite rows whether or not they changed, so unchanged updates are skipped.
+-- `now()` is the start of the transaction, so everything one sync changes shares a timestamp.
+CREATE OR REPLACE FUNCTION record_list_history() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND
+        (OLD.user_title, OLD.start_day, OLD.end_day, OLD.score, OLD.status, OLD.progress)
+        IS NOT DISTINCT FROM
+        (NEW.user_title, NEW.start_day, NEW.end_day, NEW.score, NEW.status, NEW.progress) THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE list_history SET valid_to = now()
+        WHERE user_id = OLD.user_id AND anime_id = OLD.anime_id AND valid_to IS NULL;
+    END IF;
+
+    IF TG_OP <> 'DELETE' THEN
+        INSERT INTO list_history
+            (user_id, anime_id, user_title, start_day, end_day, score, status, progress, valid_from)
+        VALUES
+            (NEW.user_id, NEW.anime_id, NEW.user_title, NEW.start_day, NEW.end_day, NEW.score,
+            NEW.status, NEW.progress, now());
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS lists_history ON lists;
+CREATE TRIGGER lists_history AFTER INSERT OR UPDATE OR DELETE ON lists
+    FOR EACH ROW EXECUTE PROCEDURE record_list_history();
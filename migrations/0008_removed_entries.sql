@@ -0,0 +1,35 @@
+-- Entries that disappear from a user's provider are marked removed rather than deleted, so their
+-- dates survive an accidental removal. They are purged once the grace period has passed.
+ALTER TABLE lists ADD COLUMN IF NOT EXISTS removed_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS lists_removed_at ON lists (removed_at) WHERE removed_at IS NOT NULL;
+
+-- Removed entries aren't on the list, so removing one closes its history and restoring it starts a
+-- new version.
+CREATE OR REPLACE FUNCTION record_list_history() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND
+        (OLD.user_title, OLD.start_day, OLD.end_day, OLD.score, OLD.status, OLD.progress,
+            OLD.removed_at IS NULL)
+        IS NOT DISTINCT FROM
+        (NEW.user_title, NEW.start_day, NEW.end_day, NEW.score, NEW.status, NEW.progress,
+            NEW.removed_at IS NULL) THEN
+        RETURN NULL;
+    END IF;
+
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE list_history SET valid_to = now()
+        WHERE user_id = OLD.user_id AND anime_id = OLD.anime_id AND valid_to IS NULL;
+    END IF;
+
+    IF TG_OP <> 'DELETE' AND NEW.removed_at IS NULL THEN
+        INSERT INTO list_history
+            (user_id, anime_id, user_title, start_day, end_day, score, status, progress, valid_from)
+        VALUES
+            (NEW.user_id, NEW.anime_id, NEW.user_title, NEW.start_day, NEW.end_day, NEW.score,
+            NEW.status, NEW.progress, now());
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,11 @@
+-- What anime are searched by: titles, which can be in any language and so aren't stemmed, weighted
+-- above descriptions. Queries have to use the same expression for the index to be used.
+CREATE OR REPLACE FUNCTION anime_document(romaji text, english text, native text, description text)
+RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector('simple',
+            coalesce(romaji, '') || ' ' || coalesce(english, '') || ' ' || coalesce(native, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(description, '')), 'B')
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE INDEX IF NOT EXISTS anime_search ON anime
+    USING gin (anime_document(romaji, english, native, description));
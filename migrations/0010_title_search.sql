@@ -0,0 +1,16 @@
+-- Searching a user's list by title, matching whole words through full-text search and misspelled
+-- or partial ones through trigrams. Titles are in any language, so none of them are stemmed.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE OR REPLACE FUNCTION anime_titles(romaji text, english text, native text) RETURNS text AS $$
+    SELECT coalesce(romaji, '') || ' ' || coalesce(english, '') || ' ' || coalesce(native, '')
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE INDEX IF NOT EXISTS anime_titles_search ON anime
+    USING gin (to_tsvector('simple', anime_titles(romaji, english, native)));
+CREATE INDEX IF NOT EXISTS anime_titles_trigram ON anime
+    USING gin (anime_titles(romaji, english, native) gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS lists_user_title_search ON lists
+    USING gin (to_tsvector('simple', coalesce(user_title, '')));
+CREATE INDEX IF NOT EXISTS lists_user_title_trigram ON lists USING gin (user_title gin_trgm_ops);
@@ -0,0 +1,8 @@
+-- Descriptions are sanitized when they are stored, alongside a plain text version and a short
+-- summary of it. Sanitizing happens in the server, so it fills these in for anime stored before
+-- this migration, which it finds by their missing plain text.
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS description_text text;
+ALTER TABLE anime ADD COLUMN IF NOT EXISTS description_summary text;
+
+CREATE INDEX IF NOT EXISTS anime_description_text_missing ON anime (anime_id)
+    WHERE description_text IS NULL;
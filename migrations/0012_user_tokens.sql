@@ -0,0 +1,3 @@
+-- Local users have no provider to vouch for them, so whoever made one gets a token that is needed
+-- to write to their list again. Only its hash is stored.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS token_hash bytea;
@@ -0,0 +1,5 @@
+-- Users without an id of their own get negative ones counting down from -1. Taking them from a
+-- sequence keeps two imports from picking the same one.
+CREATE SEQUENCE IF NOT EXISTS local_user_ids INCREMENT BY -1 MAXVALUE -1 START WITH -1;
+
+SELECT setval('local_user_ids', min(user_id)) FROM users HAVING min(user_id) < 0;
@@ -0,0 +1,8 @@
+-- When an update was last asked for under each name, whether or not the user exists or the sync
+-- got anywhere, so asking again can't fetch from a provider more often than the cooldown allows.
+CREATE TABLE IF NOT EXISTS update_attempts (
+    provider text NOT NULL,
+    name text NOT NULL,
+    attempted_at timestamptz NOT NULL,
+    PRIMARY KEY (provider, name)
+);
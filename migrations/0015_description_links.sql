@@ -0,0 +1,4 @@
+-- Sanitizing now keeps only http, https and mailto links, and plain text breaks lines at paragraphs
+-- and lists. Clearing the plain text has the server sanitize every stored description again and
+-- rebuild its plain text and summary.
+UPDATE anime SET description_text = NULL, description_summary = NULL;
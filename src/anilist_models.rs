@@ -58,6 +58,23 @@ pub struct MediaListCollection {
     pub lists: Vec<MediaList>,
 }
 
+// Media Lookup Structs
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageResponse {
+    pub data: PageData,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageData {
+    #[serde(rename = "Page")]
+    pub page: Page,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Page {
+    pub media: Vec<Media>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Avatar {
     pub large: String,
@@ -73,6 +90,8 @@ pub struct MediaList {
 pub struct Entry {
     #[serde(rename = "scoreRaw")]
     pub score_raw: Option<i16>,
+    pub status: Option<String>,
+    pub progress: Option<i32>,
     #[serde(rename = "startedAt")]
     pub started_at: Date,
     #[serde(rename = "completedAt")]
@@ -90,12 +109,17 @@ pub struct Date {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Media {
     pub id: i32,
+    #[serde(rename = "idMal")]
+    pub id_mal: Option<i32>,
     pub title: Title,
     pub description: String,
     #[serde(rename = "coverImage")]
     pub cover_image: Image,
     #[serde(rename = "averageScore")]
     pub average_score: Option<i16>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
     #[serde(rename = "siteUrl")]
     pub site_url: String,
 }
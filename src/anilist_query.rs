@@ -7,11 +7,82 @@
  */
 
 use crate::anilist_models;
+use crate::provider::{ListProvider, ProviderEntry, ProviderMedia, ProviderUser};
+use chrono::NaiveDate;
 use log::error;
 use reqwest::blocking::Client;
 use serde_json::from_str;
 use std::collections::HashMap;
 
+pub struct AniList;
+
+impl ListProvider for AniList {
+    fn name(&self) -> &'static str {
+        "anilist"
+    }
+
+    fn get_user(&self, username: &str) -> Option<ProviderUser> {
+        get_id(username).map(|user| ProviderUser {
+            id: user.id,
+            name: user.name,
+            avatar: user.avatar.large,
+        })
+    }
+
+    fn get_entries(&self, user_id: i32) -> Option<Vec<ProviderEntry>> {
+        let lists = get_lists(user_id)?;
+        let mut entries = Vec::new();
+
+        for list in lists {
+            if list.name.to_lowercase().contains("completed")
+                || list.name.to_lowercase().contains("watching")
+            {
+                for entry in list.entries {
+                    entries.push(ProviderEntry {
+                        status: entry.status,
+                        progress: entry.progress,
+                        score: entry.score_raw,
+                        start_day: construct_date(entry.started_at),
+                        end_day: construct_date(entry.completed_at),
+                        media: entry.media.into(),
+                    });
+                }
+            }
+        }
+
+        Some(entries)
+    }
+
+    // The whole list comes back from one query.
+    fn requests_for(&self, _entries: usize) -> usize {
+        1
+    }
+
+    // AniList users have always been stored under their AniList id.
+    fn stores_external_id_as_user_id(&self) -> bool {
+        true
+    }
+}
+
+impl From<anilist_models::Media> for ProviderMedia {
+    fn from(media: anilist_models::Media) -> ProviderMedia {
+        ProviderMedia {
+            id: media.id,
+            mal_id: media.id_mal,
+            user_title: media.title.user_preferred,
+            english: media.title.english,
+            romaji: media.title.romaji,
+            native: media.title.native,
+            description: media.description,
+            cover: media.cover_image.large,
+            average: media.average_score,
+            format: media.format,
+            episodes: media.episodes,
+            duration: media.duration,
+        }
+    }
+}
+
 pub fn get_id(username: &str) -> Option<anilist_models::User> {
     // Construct query to anilist GraphQL to find corresponding id for username
     let query = USER_QUERY.replace("{}", username.as_ref());
@@ -35,16 +106,71 @@ pub fn get_id(username: &str) -> Option<anilist_models::User> {
     }
 }
 
-pub fn get_lists(id: i32) -> Vec<anilist_models::MediaList> {
+pub fn get_lists(id: i32) -> Option<Vec<anilist_models::MediaList>> {
     let query = LIST_QUERY.replace("{}", id.to_string().as_ref());
     let mut body = HashMap::new();
     body.insert("query", query);
 
     let client = Client::new();
-    let res = client.post(ANILSIT_URL).json(&body).send().unwrap();
-    let res_text = res.text().unwrap();
-    let json: anilist_models::ListResponse = from_str(res_text.as_ref()).unwrap();
-    json.data.media_list_collection.lists.clone()
+    let res_text = client
+        .post(ANILSIT_URL)
+        .json(&body)
+        .send()
+        .and_then(|res| res.text());
+
+    match res_text.map(|text| from_str::<anilist_models::ListResponse>(text.as_ref())) {
+        Ok(Ok(json)) => Some(json.data.media_list_collection.lists),
+        Ok(Err(error)) => {
+            error!("error parsing lists for user_id={}. Error: {}", id, error);
+            None
+        }
+        Err(error) => {
+            error!("error fetching lists for user_id={}. Error: {}", id, error);
+            None
+        }
+    }
+}
+
+// The most media AniList will return per request.
+pub static MAL_PAGE_SIZE: usize = 50;
+
+// Looks up media by their MyAnimeList ids, a page at a time. Fails if any page can't be fetched or
+// read, rather than leaving its media out.
+pub fn get_media_by_mal_ids(mal_ids: &[i32]) -> Result<Vec<ProviderMedia>, String> {
+    let client = Client::new();
+    let mut media = Vec::with_capacity(mal_ids.len());
+
+    for chunk in mal_ids.chunks(MAL_PAGE_SIZE) {
+        let ids: Vec<String> = chunk.iter().map(|id| id.to_string()).collect();
+        let query = MAL_MEDIA_QUERY.replace("{}", ids.join(",").as_ref());
+        let mut body = HashMap::new();
+        body.insert("query", query);
+
+        let res_text = client
+            .post(ANILSIT_URL)
+            .json(&body)
+            .send()
+            .and_then(|res| res.text())
+            .map_err(|error| format!("error fetching mal_ids={:?}. Error: {}", chunk, error))?;
+        let json = from_str::<anilist_models::PageResponse>(res_text.as_ref())
+            .map_err(|error| format!("error parsing mal_ids={:?}. Error: {}", chunk, error))?;
+        media.extend(json.data.page.media.into_iter().map(ProviderMedia::from));
+    }
+
+    Ok(media)
+}
+
+fn construct_date(date: anilist_models::Date) -> Option<NaiveDate> {
+    match date.year {
+        Some(year) => match date.month {
+            Some(month) => match date.day {
+                Some(day) => NaiveDate::from_ymd_opt(year, month as u32, day as u32),
+                None => None,
+            },
+            None => None,
+        },
+        None => None,
+    }
 }
 
 static ANILSIT_URL: &'static str = "https://graphql.anilist.co";
@@ -62,6 +188,8 @@ static LIST_QUERY: &'static str = "query {
 
   fragment mediaListEntry on MediaList {
     scoreRaw: score(format: POINT_100)
+    status
+    progress
     startedAt {
       year
       month
@@ -74,6 +202,7 @@ static LIST_QUERY: &'static str = "query {
     }
     media {
 	  id
+      idMal
       title {
         userPreferred
         english
@@ -85,10 +214,37 @@ static LIST_QUERY: &'static str = "query {
         large
       }
       averageScore
+      format
+      episodes
+      duration
       siteUrl
       }
     }";
 
+static MAL_MEDIA_QUERY: &'static str = "query {
+    Page(perPage: 50) {
+      media(idMal_in: [{}], type: ANIME) {
+        id
+        idMal
+        title {
+          userPreferred
+          english
+          romaji
+          native
+        }
+        description(asHtml: true)
+        coverImage {
+          large
+        }
+        averageScore
+        format
+        episodes
+        duration
+        siteUrl
+      }
+    }
+  }";
+
 static USER_QUERY: &'static str = "query {
   	User(name: \"{}\") {
 	  id
@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use log::error;
+use rocket::request::{FromRequest, Outcome, Request};
+use std::fmt::Write;
+
+/// A new random token for proving ownership of a local user, as 64 hex characters.
+pub fn new_token() -> Option<String> {
+    let mut bytes = [0u8; 32];
+    if let Err(error) = getrandom::getrandom(&mut bytes) {
+        error!("error generating a token. Error: {}", error);
+        return None;
+    }
+
+    let mut token = String::with_capacity(bytes.len() * 2);
+    for byte in bytes.iter() {
+        let _ = write!(token, "{:02x}", byte);
+    }
+    Some(token)
+}
+
+/// The token a request was sent with as `Authorization: Bearer <token>`, if any.
+pub struct BearerToken(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for BearerToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<BearerToken, ()> {
+        Outcome::Success(BearerToken(
+            request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|header| header.strip_prefix("Bearer "))
+                .map(|token| token.trim().to_owned())
+                .filter(|token| !token.is_empty()),
+        ))
+    }
+}
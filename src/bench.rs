@@ -0,0 +1,87 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::provider::{ProviderEntry, ProviderMedia};
+use crate::{database, models, repository};
+use chrono::NaiveDate;
+use postgres::Connection;
+use std::time::{Duration, Instant};
+
+pub static DEFAULT_SIZE: usize = 5000;
+
+/// Times the database side of a sync for a generated list of `size` entries. The first sync stores
+/// every entry, and the second changes most of them, drops a tenth and adds as many new ones.
+/// Everything happens in a transaction that is rolled back, so the database is left as it was.
+pub fn bench_sync(size: usize, connection: &Connection) -> Result<(), String> {
+    let transaction = connection.transaction().map_err(|e| e.to_string())?;
+    let user_id = repository::next_local_id(&transaction).map_err(|e| e.to_string())?;
+    let user = models::User {
+        user_id,
+        name: format!("bench-sync{}", user_id),
+        avatar_s3: String::new(),
+        avatar_anilist: String::new(),
+        provider: "local".to_owned(),
+        external_id: None,
+    };
+    repository::upsert(&user, &transaction).map_err(|e| e.to_string())?;
+
+    let run_id = repository::start_sync_run(user_id, &transaction).map_err(|e| e.to_string())?;
+
+    let initial: Vec<ProviderEntry> = (0..size).map(|i| fixture_entry(i, 0)).collect();
+    let start = Instant::now();
+    database::sync_entries(user_id, run_id, initial, &transaction).map_err(|e| e.to_string())?;
+    report("initial sync", size, start.elapsed());
+
+    let dropped = size / 10;
+    let resync: Vec<ProviderEntry> = (dropped..size + dropped)
+        .map(|i| fixture_entry(i, 1))
+        .collect();
+    let start = Instant::now();
+    database::sync_entries(user_id, run_id, resync, &transaction).map_err(|e| e.to_string())?;
+    report("resync", size, start.elapsed());
+
+    let stored = repository::find_list_items(user_id, &transaction).map_err(|e| e.to_string())?;
+    if stored.len() != size {
+        return Err(format!(
+            "Expected {} stored entries after the resync, found {}",
+            size,
+            stored.len()
+        ));
+    }
+    Ok(())
+}
+
+fn report(name: &str, size: usize, elapsed: Duration) {
+    println!("{}: {} entries in {} ms", name, size, elapsed.as_millis());
+}
+
+// Generated anime get negative ids so they can never touch real ones, even before the rollback.
+fn fixture_entry(i: usize, round: i16) -> ProviderEntry {
+    let id = -(i as i32) - 1;
+    ProviderEntry {
+        media: ProviderMedia {
+            id,
+            mal_id: Some(i as i32 + 1),
+            user_title: Some(format!("Benchmark Anime {}", i)),
+            english: Some(format!("Benchmark Anime {}", i)),
+            romaji: Some(format!("Benchmark Anime {}", i)),
+            native: None,
+            description: format!("Generated entry {} for the sync benchmark.", i),
+            cover: String::new(),
+            average: Some(70 + round),
+            format: Some("TV".to_owned()),
+            episodes: Some(12),
+            duration: Some(24),
+        },
+        status: Some("COMPLETED".to_owned()),
+        progress: Some(12),
+        score: Some(((i % 10) as i16 + 1) * 10 - round),
+        start_day: NaiveDate::from_ymd_opt(2015 + (i % 5) as i32, 1 + (i % 12) as u32, 1),
+        end_day: NaiveDate::from_ymd_opt(2015 + (i % 5) as i32, 1 + (i % 12) as u32, 20),
+    }
+}
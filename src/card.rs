@@ -0,0 +1,296 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::timeline::{self, ColorBy};
+use crate::{html, models};
+use chrono::{Local, NaiveDate};
+use dotenv::dotenv;
+use log::error;
+use reqwest::blocking::get;
+use reqwest::header::CONTENT_TYPE;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+// Open Graph's recommended image size.
+static CARD_WIDTH: u32 = 1200;
+static CARD_HEIGHT: u32 = 630;
+static LANES: usize = 8;
+static LANE_HEIGHT: f64 = 12.0;
+
+/// Returns the PNG share card for a list, rendering it only if the list has changed since the
+/// cached card was made.
+pub fn get_card(list: &models::ResponseList) -> Option<Vec<u8>> {
+    let dir = cache_dir();
+    let prefix = format!("{}_", list.id.to_lowercase());
+    let path = dir.join(format!("{}{:016x}.png", prefix, fingerprint(list)));
+
+    if let Ok(png) = fs::read(&path) {
+        return Some(png);
+    }
+
+    let png = rasterize(&render(list))?;
+
+    clear_cached(&dir, &prefix);
+    if let Err(error) = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, &png)) {
+        error!("error caching card at {:?}. Error: {}", path, error);
+    }
+
+    Some(png)
+}
+
+fn render(list: &models::ResponseList) -> String {
+    let completed: Vec<&models::ResponseItem> =
+        list.list.iter().filter(|item| is_completed(item)).collect();
+    let minutes: i64 = list.list.iter().map(watched_minutes).sum();
+
+    let mut top: Vec<&models::ResponseItem> = list
+        .list
+        .iter()
+        .filter(|item| item.score.unwrap_or(0) > 0)
+        .collect();
+    top.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(timeline::title(a).cmp(timeline::title(b)))
+    });
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\">\
+         <rect width=\"100%\" height=\"100%\" fill=\"#151f2e\"/>",
+        w = CARD_WIDTH,
+        h = CARD_HEIGHT
+    );
+
+    if let Some(avatar) = avatar_data_uri(&list.avatar) {
+        let _ = write!(
+            svg,
+            "<clipPath id=\"avatar\"><circle cx=\"140\" cy=\"140\" r=\"90\"/></clipPath>\
+             <image x=\"50\" y=\"50\" width=\"180\" height=\"180\" clip-path=\"url(#avatar)\" \
+             preserveAspectRatio=\"xMidYMid slice\" xlink:href=\"{}\"/>",
+            avatar
+        );
+    }
+
+    let _ = write!(
+        svg,
+        "<text x=\"270\" y=\"120\" font-size=\"64\" font-weight=\"bold\" fill=\"#ffffff\">{}</text>\
+         <text x=\"270\" y=\"190\" font-size=\"32\" fill=\"#9fadbd\">{} completed · {} hours</text>",
+        html::escape(&list.id),
+        completed.len(),
+        minutes / 60
+    );
+
+    for (rank, item) in top.iter().take(3).enumerate() {
+        let _ = write!(
+            svg,
+            "<text x=\"50\" y=\"{}\" font-size=\"30\" fill=\"#ffffff\">{}. {} \
+             <tspan fill=\"#3db4f2\">{}</tspan></text>",
+            300 + rank * 50,
+            rank + 1,
+            html::escape(&truncate(timeline::title(item), 50)),
+            item.score.unwrap_or(0)
+        );
+    }
+
+    render_mini_timeline(&mut svg, list, 50.0, 470.0, CARD_WIDTH as f64 - 100.0);
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Draws every dated entry as a thin bar, packing overlapping entries into a fixed number of lanes.
+fn render_mini_timeline(svg: &mut String, list: &models::ResponseList, x: f64, y: f64, width: f64) {
+    let today = Local::now().naive_local().date();
+    let mut bars: Vec<(NaiveDate, NaiveDate, &models::ResponseItem)> = list
+        .list
+        .iter()
+        .filter_map(|item| {
+            let start = item.start_day?;
+            Some((start, item.end_day.unwrap_or(today).max(start), item))
+        })
+        .collect();
+    bars.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let (from, to) = match (bars.first(), bars.iter().map(|b| b.1).max()) {
+        (Some(first), Some(last)) => (first.0, last),
+        _ => return,
+    };
+    let scale = width / ((to - from).num_days() + 1) as f64;
+
+    let _ = write!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" font-size=\"22\" fill=\"#9fadbd\">{} – {}</text>",
+        x,
+        y - 12.0,
+        from.format("%b %Y"),
+        to.format("%b %Y")
+    );
+
+    let mut lane_ends = vec![from; LANES];
+    for (start, end, item) in bars {
+        // Use the first free lane, or the one that frees up soonest if every lane is busy.
+        let lane = (0..LANES)
+            .find(|&lane| lane_ends[lane] < start)
+            .unwrap_or_else(|| (0..LANES).min_by_key(|&lane| lane_ends[lane]).unwrap_or(0));
+        lane_ends[lane] = end;
+
+        let _ = write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{}\" rx=\"2\" fill=\"{}\"/>",
+            x + (start - from).num_days() as f64 * scale,
+            y + LANE_HEIGHT * 1.5 * lane as f64,
+            ((end - start).num_days() + 1) as f64 * scale,
+            LANE_HEIGHT,
+            timeline::fill(item, &ColorBy::Score)
+        );
+    }
+}
+
+fn rasterize(svg: &str) -> Option<Vec<u8>> {
+    let mut opt = usvg::Options::default();
+    opt.fontdb.load_system_fonts();
+
+    let tree = match usvg::Tree::from_str(svg, &opt.to_ref()) {
+        Ok(tree) => tree,
+        Err(error) => {
+            error!("error parsing card svg. Error: {}", error);
+            return None;
+        }
+    };
+
+    let mut pixmap = tiny_skia::Pixmap::new(CARD_WIDTH, CARD_HEIGHT)?;
+    resvg::render(
+        &tree,
+        usvg::FitTo::Original,
+        tiny_skia::Transform::default(),
+        pixmap.as_mut(),
+    )?;
+
+    match pixmap.encode_png() {
+        Ok(png) => Some(png),
+        Err(error) => {
+            error!("error encoding card png. Error: {}", error);
+            None
+        }
+    }
+}
+
+fn avatar_data_uri(url: &str) -> Option<String> {
+    let resp = match get(url) {
+        Ok(resp) if resp.status().is_success() => resp,
+        _ => {
+            error!("error downloading avatar={} for card", url);
+            return None;
+        }
+    };
+    let mime = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("image/png")
+        .to_owned();
+    let content = resp.bytes().ok()?;
+    Some(format!("data:{};base64,{}", mime, base64::encode(&content)))
+}
+
+// Older rows were synced before list status was stored, so fall back to having an end date.
+fn is_completed(item: &models::ResponseItem) -> bool {
+    match item.status.as_deref() {
+        Some(status) => status == "COMPLETED",
+        None => item.end_day.is_some(),
+    }
+}
+
+fn watched_minutes(item: &models::ResponseItem) -> i64 {
+    let episodes = match item.progress {
+        Some(progress) if progress > 0 => progress,
+        _ if is_completed(item) => item.episodes.unwrap_or(0),
+        _ => 0,
+    };
+    episodes as i64 * item.duration.unwrap_or(0) as i64
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() > max_chars {
+        let mut truncated: String = text.chars().take(max_chars - 1).collect();
+        truncated.push('…');
+        truncated
+    } else {
+        text.to_owned()
+    }
+}
+
+// A list only changes when it is synced or imported, so the time of that and whose list it is are
+// enough to tell a stale card apart.
+fn fingerprint(list: &models::ResponseList) -> u64 {
+    let synced = list
+        .last_synced_at
+        .map_or(0, |synced| synced.timestamp_millis());
+    fnv1a(format!("{}\n{}", list.id.to_lowercase(), synced).as_bytes())
+}
+
+// 64 bit FNV-1a. Unlike `DefaultHasher` it gives the same hash on every Rust release, so cached
+// cards stay valid across toolchain upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn clear_cached(dir: &Path, prefix: &str) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // A 16 character fingerprint and ".png" follow the prefix.
+            if name.starts_with(prefix) && name.len() == prefix.len() + 20 {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+fn cache_dir() -> PathBuf {
+    dotenv().ok();
+
+    PathBuf::from(env::var("CARD_CACHE_DIR").unwrap_or_else(|_| "cards".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn list(id: &str, synced: Option<i64>) -> models::ResponseList {
+        models::ResponseList {
+            id: id.to_owned(),
+            avatar: String::new(),
+            last_synced_at: synced.map(|seconds| Utc.timestamp_opt(seconds, 0).unwrap()),
+            list: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn hashes_with_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn fingerprint_changes_with_each_sync() {
+        let synced = fingerprint(&list("Foo", Some(1_600_000_000)));
+
+        assert_eq!(synced, fingerprint(&list("foo", Some(1_600_000_000))));
+        assert_ne!(synced, fingerprint(&list("Foo", Some(1_600_000_001))));
+        assert_ne!(synced, fingerprint(&list("Foo", None)));
+        assert_ne!(synced, fingerprint(&list("Bar", Some(1_600_000_000))));
+    }
+}
@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::models::{ListChange, ListItem, SyncSummary};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Compares a user's stored list with the one a sync is about to save. Titles aren't user edits,
+/// so only the status, progress, score and dates are compared.
+pub fn diff(
+    run_id: i32,
+    stored: &[ListItem],
+    synced: &[ListItem],
+    at: DateTime<Utc>,
+) -> Vec<ListChange> {
+    let stored_by_id: HashMap<i32, &ListItem> =
+        stored.iter().map(|item| (item.anime_id, item)).collect();
+    let synced_by_id: HashMap<i32, &ListItem> =
+        synced.iter().map(|item| (item.anime_id, item)).collect();
+
+    let change = |item: &ListItem, change: &str, field: Option<&str>, old, new| ListChange {
+        run_id,
+        user_id: item.user_id,
+        anime_id: item.anime_id,
+        changed_at: at,
+        change: change.to_owned(),
+        field: field.map(|field| field.to_owned()),
+        old_value: old,
+        new_value: new,
+    };
+
+    let mut changes = Vec::new();
+    for item in synced {
+        match stored_by_id.get(&item.anime_id) {
+            None => changes.push(change(item, "added", None, None, None)),
+            Some(old) => {
+                for ((field, old_value), (_, new_value)) in
+                    fields(old).into_iter().zip(fields(item))
+                {
+                    if old_value != new_value {
+                        changes.push(change(item, "updated", Some(field), old_value, new_value));
+                    }
+                }
+            }
+        }
+    }
+    for item in stored {
+        if !synced_by_id.contains_key(&item.anime_id) {
+            changes.push(change(item, "removed", None, None, None));
+        }
+    }
+    changes
+}
+
+/// Fills in the dates and score of entries coming back onto a list from their removed rows when
+/// they are missing, so an entry removed by accident and added back keeps what was stored for it.
+/// Providers send an unscored entry's score as zero or not at all.
+pub fn revive(synced: &mut [ListItem], removed: &[ListItem]) {
+    let removed_by_id: HashMap<i32, &ListItem> =
+        removed.iter().map(|item| (item.anime_id, item)).collect();
+
+    for item in synced {
+        if let Some(old) = removed_by_id.get(&item.anime_id) {
+            item.start_day = item.start_day.or(old.start_day);
+            item.end_day = item.end_day.or(old.end_day);
+            if item.score.unwrap_or(0) == 0 {
+                item.score = old.score.or(item.score);
+            }
+        }
+    }
+}
+
+/// Counts how many entries were added, updated and removed.
+pub fn summarize(changes: &[ListChange]) -> SyncSummary {
+    let mut updated: Vec<i32> = changes
+        .iter()
+        .filter(|change| change.change == "updated")
+        .map(|change| change.anime_id)
+        .collect();
+    updated.dedup();
+
+    let of_kind = |kind: &str| changes.iter().filter(|c| c.change == kind).count() as i32;
+    SyncSummary {
+        added: of_kind("added"),
+        updated: updated.len() as i32,
+        removed: of_kind("removed"),
+        images_uploaded: 0,
+    }
+}
+
+fn fields(item: &ListItem) -> Vec<(&'static str, Option<String>)> {
+    vec![
+        ("status", item.status.clone()),
+        (
+            "progress",
+            item.progress.map(|progress| progress.to_string()),
+        ),
+        ("score", item.score.map(|score| score.to_string())),
+        ("start_day", item.start_day.map(|day| day.to_string())),
+        ("end_day", item.end_day.map(|day| day.to_string())),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+
+    fn item(anime_id: i32, status: &str, progress: i32) -> ListItem {
+        ListItem {
+            user_id: 1,
+            anime_id,
+            user_title: Some(format!("Anime {}", anime_id)),
+            start_day: None,
+            end_day: None,
+            score: Some(70),
+            status: Some(status.to_owned()),
+            progress: Some(progress),
+            removed_at: None,
+        }
+    }
+
+    fn at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn unchanged_lists_have_no_changes() {
+        let list = vec![item(1, "COMPLETED", 12), item(2, "CURRENT", 3)];
+        assert!(diff(7, &list, &list, at()).is_empty());
+    }
+
+    #[test]
+    fn titles_are_not_changes() {
+        let stored = vec![item(1, "COMPLETED", 12)];
+        let mut synced = stored.clone();
+        synced[0].user_title = Some("Renamed".to_owned());
+        assert!(diff(7, &stored, &synced, at()).is_empty());
+    }
+
+    #[test]
+    fn finds_added_updated_and_removed_entries() {
+        let stored = vec![item(1, "CURRENT", 3), item(2, "CURRENT", 5)];
+        let synced = vec![item(1, "COMPLETED", 12), item(3, "CURRENT", 1)];
+        let changes = diff(7, &stored, &synced, at());
+
+        let described: Vec<_> = changes
+            .iter()
+            .map(|change| {
+                (
+                    change.anime_id,
+                    change.change.as_str(),
+                    change.field.as_deref(),
+                    change.old_value.as_deref(),
+                    change.new_value.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec![
+                (
+                    1,
+                    "updated",
+                    Some("status"),
+                    Some("CURRENT"),
+                    Some("COMPLETED")
+                ),
+                (1, "updated", Some("progress"), Some("3"), Some("12")),
+                (3, "added", None, None, None),
+                (2, "removed", None, None, None),
+            ]
+        );
+        assert!(changes
+            .iter()
+            .all(|change| change.run_id == 7 && change.changed_at == at()));
+    }
+
+    #[test]
+    fn re_added_entries_keep_their_removed_details() {
+        let mut removed = item(1, "COMPLETED", 12);
+        removed.start_day = NaiveDate::from_ymd_opt(2019, 4, 1);
+        removed.end_day = NaiveDate::from_ymd_opt(2019, 6, 30);
+        removed.score = Some(85);
+        removed.removed_at = Some(at());
+
+        // Removed on the provider, then added back there without dates or a score.
+        let stored = vec![item(2, "CURRENT", 5)];
+        let mut synced = vec![item(1, "COMPLETED", 12), item(2, "CURRENT", 5)];
+        synced[0].score = Some(0);
+        revive(&mut synced, &[removed]);
+
+        assert_eq!(synced[0].start_day, NaiveDate::from_ymd_opt(2019, 4, 1));
+        assert_eq!(synced[0].end_day, NaiveDate::from_ymd_opt(2019, 6, 30));
+        assert_eq!(synced[0].score, Some(85));
+        assert_eq!(synced[1].score, Some(70));
+        let changes = diff(7, &stored, &synced, at());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            (changes[0].anime_id, changes[0].change.as_str()),
+            (1, "added")
+        );
+    }
+
+    #[test]
+    fn re_added_entries_keep_new_details() {
+        let mut removed = item(1, "COMPLETED", 12);
+        removed.start_day = NaiveDate::from_ymd_opt(2019, 4, 1);
+        let mut synced = vec![item(1, "CURRENT", 1)];
+        synced[0].start_day = NaiveDate::from_ymd_opt(2021, 1, 1);
+        revive(&mut synced, &[removed]);
+
+        assert_eq!(synced[0].start_day, NaiveDate::from_ymd_opt(2021, 1, 1));
+        assert_eq!(synced[0].score, Some(70));
+        assert_eq!(synced[0].progress, Some(1));
+    }
+
+    #[test]
+    fn summarize_counts_entries_not_fields() {
+        let stored = vec![item(1, "CURRENT", 3), item(2, "CURRENT", 5)];
+        let synced = vec![item(1, "COMPLETED", 12), item(3, "CURRENT", 1)];
+        let summary = summarize(&diff(7, &stored, &synced, at()));
+
+        assert_eq!(summary.added, 1);
+        assert_eq!(summary.updated, 1);
+        assert_eq!(summary.removed, 1);
+    }
+}
@@ -6,20 +6,24 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use crate::{anilist_models, anilist_query, models};
-use chrono::NaiveDate;
+use crate::description::{self, DescriptionFormat};
+use crate::provider::{self, ListProvider, ProviderEntry, ProviderMedia, ProviderUser};
+use crate::{auth, changes, fields, html, models, repository};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use dotenv::dotenv;
 use log::{error, info};
 use reqwest::blocking::get;
-use rocket_contrib::databases::postgres::{Connection, TlsMode};
+use rocket_contrib::databases::postgres::{Connection, GenericConnection, TlsMode};
 use rusoto_core::Region;
 use rusoto_s3::{PutObjectRequest, S3Client, S3};
+use std::collections::HashMap;
 use std::io::Read;
-use std::{env, thread, panic};
+use std::time::Instant;
+use std::{env, panic, thread};
 
-// Only used for upload_to_s3 because of spawned threads and I didn't want to make the connection
-// pool work with that.
-fn establish_connection() -> Connection {
+// Used by the spawned update threads and the command line, which both run outside of the
+// connection pool.
+pub fn establish_connection() -> Connection {
     dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -33,85 +37,137 @@ fn establish_connection() -> Connection {
     }
 }
 
-pub fn get_list(name: &str, connection: &postgres::Connection) -> Option<models::RestResponse> {
-    let stmt = connection
-	  .prepare_cached("SELECT u.user_id, u.name, u.avatar_s3, u.avatar_anilist, a.anime_id, a\
-	  .description, a.cover_s3, a.cover_anilist, a.average, a.native, a.romaji, a.english, l\
-	  .user_title, l.start_day, l.end_day, l.score FROM lists as l INNER JOIN users as u ON l\
-	  .user_id=u.user_id INNER JOIN anime as a ON l.anime_id=a.anime_id WHERE u.name = $1")
-	  .unwrap();
-
-    let results = stmt.query(&[&name]);
-
-    match results {
-        Ok(result) => {
-            let mut database_list: Vec<models::ListItemMap> = Vec::with_capacity(result.len());
-            for row in result.iter() {
-                let user = models::User {
-                    user_id: row.get(0),
-                    name: row.get(1),
-                    avatar_s3: row.get(2),
-                    avatar_anilist: row.get(3),
-                };
-
-                let anime = models::Anime {
-                    anime_id: row.get(4),
-                    description: row.get(5),
-                    cover_s3: row.get(6),
-                    cover_anilist: row.get(7),
-                    average: row.get(8),
-                    native: row.get(9),
-                    romaji: row.get(10),
-                    english: row.get(11),
-                };
-
-                let list_item = models::ListItem {
-                    user_id: row.get(0),
-                    anime_id: row.get(4),
-                    user_title: row.get(12),
-                    start_day: row.get(13),
-                    end_day: row.get(14),
-                    score: row.get(15),
-                };
-
-                database_list.push(models::ListItemMap {
-                    user,
-                    anime,
-                    list_item,
-                });
-            }
+pub fn get_list(
+    name: &str,
+    options: &models::ListOptions,
+    connection: &postgres::Connection,
+) -> Option<models::RestResponse> {
+    let requested = options.fields.as_deref().unwrap_or(&fields::LIST_FIELDS);
+    let columns = fields::list_columns(requested, options.description);
+    let database_list = match options.as_of {
+        Some(date) => get_list_items_as_of(name, date, Some(&columns), connection)?,
+        None => find_list_items(name, options.include_removed, Some(&columns), connection)?,
+    };
 
-            if database_list.len() > 0 {
-                let mut response_items: Vec<models::ResponseItem> =
-                    Vec::with_capacity(database_list.len());
-                for list_item in database_list.clone() {
-                    let item = models::ResponseItem {
-                        user_title: list_item.list_item.user_title,
-                        start_day: list_item.list_item.start_day,
-                        end_day: list_item.list_item.end_day,
-                        score: list_item.list_item.score,
-                        average: list_item.anime.average,
-                        native: list_item.anime.native,
-                        romaji: list_item.anime.romaji,
-                        english: list_item.anime.english,
-                        description: list_item.anime.description,
-                        cover: list_item.anime.cover_s3,
-                        id: list_item.anime.anime_id,
-                    };
-
-                    response_items.push(item);
-                }
-                Some(models::RestResponse {
-                    users: models::ResponseList {
-                        id: database_list[0].user.name.clone(),
-                        avatar: database_list[0].user.avatar_s3.clone(),
-                        list: response_items,
-                    },
-                })
-            } else {
+    if database_list.len() > 0 {
+        let mut response_items: Vec<models::ResponseItem> = Vec::with_capacity(database_list.len());
+        for list_item in database_list.clone() {
+            let description = describe(&list_item.anime, options.description);
+            let item = models::ResponseItem {
+                display_title: options.title.pick(
+                    list_item.list_item.user_title.as_deref(),
+                    list_item.anime.romaji.as_deref(),
+                    list_item.anime.english.as_deref(),
+                    list_item.anime.native.as_deref(),
+                ),
+                user_title: list_item.list_item.user_title,
+                start_day: list_item.list_item.start_day,
+                end_day: list_item.list_item.end_day,
+                score: list_item.list_item.score,
+                status: list_item.list_item.status,
+                progress: list_item.list_item.progress,
+                average: list_item.anime.average,
+                native: list_item.anime.native,
+                romaji: list_item.anime.romaji,
+                english: list_item.anime.english,
+                format: list_item.anime.format,
+                episodes: list_item.anime.episodes,
+                duration: list_item.anime.duration,
+                description,
+                cover: list_item.anime.cover_s3,
+                id: list_item.anime.anime_id,
+                removed_at: list_item.list_item.removed_at,
+            };
+
+            response_items.push(item);
+        }
+        if let Err(error) = repository::record_view(database_list[0].user.user_id, connection) {
+            error!(
+                "error recording view of user_name={}. Error: {}",
+                name, error
+            );
+        }
+        let sync_status = repository::find_sync_status(database_list[0].user.user_id, connection)
+            .unwrap_or_else(|error| {
+                error!(
+                    "error getting sync status for user_name={}. Error: {}",
+                    name, error
+                );
                 None
-            }
+            });
+
+        Some(models::RestResponse {
+            users: models::ResponseList {
+                id: provider::qualified_username(
+                    &database_list[0].user.provider,
+                    &database_list[0].user.name,
+                ),
+                avatar: database_list[0].user.avatar_s3.clone(),
+                last_synced_at: sync_status.and_then(|status| status.last_synced_at),
+                list: response_items,
+            },
+        })
+    } else {
+        None
+    }
+}
+
+fn describe(anime: &models::Anime, format: DescriptionFormat) -> Option<String> {
+    match format {
+        DescriptionFormat::Html => Some(anime.description.clone()),
+        DescriptionFormat::Text => Some(
+            anime
+                .description_text
+                .clone()
+                .unwrap_or_else(|| description::plain_text(&anime.description)),
+        ),
+        DescriptionFormat::Summary => {
+            Some(anime.description_summary.clone().unwrap_or_else(|| {
+                description::summary(&description::plain_text(&anime.description))
+            }))
         }
+        DescriptionFormat::Omitted => None,
+    }
+}
+
+/// Sanitizes the descriptions of anime stored before they were sanitized at ingest, or before the
+/// rules last changed, and fills in their plain text versions. Returns how many were updated.
+pub fn fill_descriptions(connection: &Connection) -> postgres::Result<usize> {
+    let mut count = 0;
+    loop {
+        let mut anime = repository::find_anime_without_description_text(500, connection)?;
+        if anime.is_empty() {
+            return Ok(count);
+        }
+        for anime in &mut anime {
+            let text = description::plain_text(&anime.description);
+            anime.description = description::sanitize(&anime.description);
+            anime.description_summary = Some(description::summary(&text));
+            anime.description_text = Some(text);
+        }
+        repository::upsert_all(&anime, connection)?;
+        count += anime.len();
+    }
+}
+
+// Gets every stored row of a user's list along with the user and anime it belongs to. The name is
+// qualified with its provider the same way as in URLs, such as `kitsu:<name>`.
+pub fn get_list_items(
+    name: &str,
+    connection: &postgres::Connection,
+) -> Option<Vec<models::ListItemMap>> {
+    find_list_items(name, false, None, connection)
+}
+
+fn find_list_items(
+    name: &str,
+    include_removed: bool,
+    only: Option<&[&str]>,
+    connection: &postgres::Connection,
+) -> Option<Vec<models::ListItemMap>> {
+    let (provider_name, user_name) = provider::split_username(name);
+    match repository::find_list(provider_name, user_name, include_removed, only, connection) {
+        Ok(database_list) => Some(database_list),
         Err(error) => {
             error!(
                 "error getting list for user_name={}. Error: {}",
@@ -122,201 +178,719 @@ pub fn get_list(name: &str, connection: &postgres::Connection) -> Option<models:
     }
 }
 
-pub fn update_user_profile(user: anilist_models::User, connection: &Connection) {
-    let ext = get_ext(&user.avatar.large);
+// Rebuilds a user's list as it was at the end of a day, UTC.
+fn get_list_items_as_of(
+    name: &str,
+    date: NaiveDate,
+    only: Option<&[&str]>,
+    connection: &postgres::Connection,
+) -> Option<Vec<models::ListItemMap>> {
+    let (provider_name, user_name) = provider::split_username(name);
+    let at = Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?) + chrono::Duration::days(1);
+    match repository::find_list_as_of(provider_name, user_name, at, only, connection) {
+        Ok(database_list) => Some(database_list),
+        Err(error) => {
+            error!(
+                "error getting list as of {} for user_name={}. Error: {}",
+                date, name, error
+            );
+            None
+        }
+    }
+}
+
+// Saves a user's profile, returning the id they are stored under. Users are identified by their
+// provider and their id there, so a renamed user keeps their list and their old name is kept as an
+// alias. AniList users keep their AniList id, while users of other providers get a local id.
+pub fn update_user_profile(
+    provider: &dyn ListProvider,
+    user: ProviderUser,
+    connection: &Connection,
+) -> Option<i32> {
+    let existing = match repository::find_user(provider.name(), user.id, &user.name, connection) {
+        Ok(existing) => existing,
+        Err(error) => {
+            error!(
+                "error finding {} user_id={}. Error: {}",
+                provider.name(),
+                user.id,
+                error
+            );
+            return None;
+        }
+    };
+
+    let user_id = match existing {
+        Some(existing) => {
+            if existing.name != user.name {
+                // Remember their previous name so links to it keep working.
+                if let Err(error) = repository::save_alias(
+                    provider.name(),
+                    &existing.name,
+                    existing.user_id,
+                    connection,
+                ) {
+                    error!(
+                        "error saving alias {}:{} for user_id={}. Error: {}",
+                        provider.name(),
+                        existing.name,
+                        existing.user_id,
+                        error
+                    );
+                }
+            }
+            existing.user_id
+        }
+        None if provider.stores_external_id_as_user_id() => user.id,
+        None => next_local_id(connection)?,
+    };
+    if let Err(error) = repository::release_name(provider.name(), &user.name, user_id, connection) {
+        error!(
+            "error releasing name {}:{} for user_id={}. Error: {}",
+            provider.name(),
+            user.name,
+            user_id,
+            error
+        );
+    }
+
+    let ext = get_ext(&user.avatar);
 
     let new_user = models::User {
-        user_id: user.id.clone(),
+        user_id,
         name: user.name.clone(),
         avatar_s3: format!(
             "https://s3.amazonaws.com/anihistory-images/assets/images/user_{}.{}",
-            user.id, ext
+            user_id, ext
         ),
-        avatar_anilist: user.avatar.large.clone(),
+        avatar_anilist: user.avatar.clone(),
+        provider: provider.name().to_owned(),
+        external_id: Some(user.id),
     };
 
-    let stmt = connection.prepare_cached("INSERT INTO users (user_id, name, avatar_s3, avatar_anilist) VALUES ($1, $2, $3, $4) ON CONFLICT (user_id) DO UPDATE SET name = excluded.name, avatar_s3 = excluded.avatar_s3, avatar_anilist = excluded.avatar_anilist").unwrap();
-
-    let result = stmt.execute(&[
-        &new_user.user_id,
-        &new_user.name,
-        &new_user.avatar_s3,
-        &new_user.avatar_anilist,
-    ]);
+    let result = repository::upsert(&new_user, connection);
 
     // Download their avatar and upload to S3.
-    let mut content = Vec::new();
-    download_image(&mut content, &user.avatar.large);
-    upload_to_s3(ImageTypes::User, user.id, ext.clone(), content);
+    if !user.avatar.is_empty() {
+        match download_image(&user.avatar) {
+            Ok(content) => {
+                upload_to_s3(ImageTypes::User, user_id, ext, content);
+            }
+            Err(error) => error!(
+                "error downloading avatar of user_id={}. Error: {}",
+                user_id, error
+            ),
+        }
+    }
 
     match result {
-        Ok(_) => (),
+        Ok(_) => Some(user_id),
         Err(error) => {
             error!("error saving user={:?}. Error: {}", new_user, error);
-            ()
+            None
+        }
+    }
+}
+
+// Finds the username a user is stored under, qualified the same way, from their name in any case or
+// from a name they used before being renamed. Current names win over aliases.
+pub fn canonical_username(username: &str, connection: &Connection) -> Option<String> {
+    let (provider_name, name) = provider::split_username(username);
+    match repository::find_user_by_name(provider_name, name, connection) {
+        Ok(user) => user.map(|user| provider::qualified_username(&user.provider, &user.name)),
+        Err(error) => {
+            error!("error resolving user_name={}. Error: {}", username, error);
+            None
         }
     }
 }
 
-pub fn delete_entries(lists: Vec<anilist_models::MediaList>, id: i32) {
+// Replaces a user's stored list with the entries fetched from their provider. The whole sync is one
+// transaction, so readers see either the old list or the new one and a failure leaves it untouched.
+pub fn update_entries(provider: &dyn ListProvider, id: i32, external_id: i32) {
+    // Another server may be syncing the same user, so wait for it to finish before fetching. The
+    // lock is held until the connection is dropped.
     let connection = establish_connection();
-    let mut used_lists = Vec::new();
-
-    for mut list in lists {
-        if list.name.to_lowercase().contains("completed")
-            || list.name.to_lowercase().contains("watching")
-        {
-            list.entries
-                .sort_unstable_by(|a, b| a.media.id.cmp(&b.media.id));
-            used_lists.push(list.clone());
-        }
-    }
-
-    let stmt = connection.prepare_cached("SELECT user_id, anime_id, user_title, start_day, end_day, score FROM lists WHERE user_id = $1").unwrap();
-
-    let user_db_list_result = stmt.query(&[&id]);
-
-    match user_db_list_result {
-        Ok(rows) => {
-            for row in rows.iter() {
-                let list_item = models::ListItem {
-                    user_id: row.get(0),
-                    anime_id: row.get(1),
-                    user_title: row.get(2),
-                    start_day: row.get(3),
-                    end_day: row.get(4),
-                    score: row.get(5),
-                };
-
-                let mut found = false;
-
-                for list in used_lists.clone() {
-                    let result = list
-                        .entries
-                        .binary_search_by(|e| e.media.id.cmp(&list_item.anime_id));
-                    match result {
-                        Ok(_) => found = true,
-                        Err(_) => {}
-                    }
-                }
+    if let Err(error) = repository::lock_user(id, &connection) {
+        error!("error locking user_id={}. Error: {}", id, error);
+        return;
+    }
 
-                if !found {
-                    println!("deleting anime:{}", list_item.anime_id);
-                    let stmt = connection
-                        .prepare_cached("DELETE FROM lists WHERE user_id = $1 AND anime_id = $2")
-                        .unwrap();
-
-                    let delete_result = stmt.execute(&[&list_item.user_id, &list_item.anime_id]);
-
-                    if delete_result.is_err() {
-                        error!(
-                            "error deleting list_entry={:?}. Error: {}",
-                            row,
-                            delete_result.expect_err("?")
-                        );
-                    }
-                }
-            }
+    let start = Instant::now();
+    let run_id = match repository::start_sync_run(id, &connection) {
+        Ok(run_id) => run_id,
+        Err(error) => {
+            error!(
+                "error starting sync run for user_id={}. Error: {}",
+                id, error
+            );
+            return;
         }
-        Err(err) => {
-            error!("error retrieving list for user_id={:?}. Error: {}", id, err);
+    };
+
+    let result = match provider.get_entries(external_id) {
+        Some(entries) => connection
+            .transaction()
+            .and_then(|transaction| {
+                let synced = sync_entries(id, run_id, entries, &transaction)?;
+                transaction.commit()?;
+                Ok(synced)
+            })
+            .map_err(|error| format!("changes rolled back: {}", error)),
+        None => Err(format!("could not get entries from {}", provider.name())),
+    };
+
+    let succeeded = result.is_ok();
+    let (summary, status, covers) = match result {
+        Ok((summary, covers)) => {
+            info!("Database updated for user_id={}", id);
+            (summary, "ok".to_owned(), covers)
+        }
+        Err(error) => {
+            error!(
+                "error updating user_id={}, list left as is. Error: {}",
+                id, error
+            );
+            (models::SyncSummary::default(), error, Vec::new())
+        }
+    };
+
+    let error = if succeeded {
+        None
+    } else {
+        Some(status.as_ref())
+    };
+    if let Err(error) = repository::finish_sync_run(run_id, &summary, error, &connection) {
+        error!("error finishing sync run_id={}. Error: {}", run_id, error);
+    }
+
+    let duration = start.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    if let Err(error) = repository::record_sync(id, succeeded, &status, duration, &connection) {
+        error!("error recording sync for user_id={}. Error: {}", id, error);
+    }
+
+    // Covers are uploaded once the sync is recorded, so slow or failing uploads can't leave it
+    // unfinished.
+    if !covers.is_empty() {
+        let uploaded = upload_covers(covers);
+        if let Err(error) = repository::set_images_uploaded(run_id, uploaded, &connection) {
+            error!(
+                "error counting uploads of run_id={}. Error: {}",
+                run_id, error
+            );
         }
     }
 }
 
-pub fn update_entries(id: i32) {
-    let lists: Vec<anilist_models::MediaList> = anilist_query::get_lists(id);
+// When an update of a user was last asked for or their latest sync started, if ever. Users are
+// found the same way as their list.
+pub fn get_last_update(username: &str, connection: &Connection) -> Option<DateTime<Utc>> {
+    let (provider_name, name) = provider::split_username(username);
+    let result =
+        repository::find_update_attempt(provider_name, name, connection).and_then(|attempted| {
+            let started = match repository::find_user_by_name(provider_name, name, connection)? {
+                Some(user) => repository::find_last_sync_start(user.user_id, connection)?,
+                None => None,
+            };
+            Ok(attempted.max(started))
+        });
+
+    match result {
+        Ok(last_update) => last_update,
+        Err(error) => {
+            error!(
+                "error getting last update for user_name={}. Error: {}",
+                username, error
+            );
+            None
+        }
+    }
+}
 
-    delete_entries(lists.clone(), id);
-    let connection = establish_connection();
+// Records an update of a user being asked for, unless another one was after `since`.
+pub fn claim_update(username: &str, since: DateTime<Utc>, connection: &Connection) -> bool {
+    let (provider_name, name) = provider::split_username(username);
+    match repository::claim_update_attempt(provider_name, name, since, connection) {
+        Ok(claimed) => claimed,
+        Err(error) => {
+            error!(
+                "error recording update attempt for user_name={}. Error: {}",
+                username, error
+            );
+            false
+        }
+    }
+}
 
-    for list in lists {
-        if list.name.to_lowercase().contains("completed")
-            || list.name.to_lowercase().contains("watching")
-        {
-            for entry in list.entries {
-                let ext = get_ext(&entry.media.cover_image.large);
-
-                let new_anime = models::Anime {
-                    anime_id: entry.media.id,
-                    description: entry.media.description,
-                    cover_s3: format!(
-                        "https://s3.amazonaws.com/anihistory-images/assets/images/anime_{}.{}",
-                        entry.media.id, ext
-                    ),
-                    cover_anilist: entry.media.cover_image.large.clone(),
-                    average: entry.media.average_score,
-                    native: entry.media.title.native,
-                    romaji: entry.media.title.romaji,
-                    english: entry.media.title.english,
-                };
-
-                let stmt = connection.prepare_cached("INSERT INTO anime (anime_id, description, cover_s3, cover_anilist, average, native, romaji, english) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (anime_id) DO UPDATE SET description = excluded.description, cover_s3 = excluded.cover_s3, cover_anilist = excluded.cover_anilist, average = excluded.average, native = excluded.native, romaji = excluded.romaji, english = excluded.english").unwrap();
-
-                let anime_result = stmt.execute(&[
-                    &new_anime.anime_id,
-                    &new_anime.description,
-                    &new_anime.cover_s3,
-                    &new_anime.cover_anilist,
-                    &new_anime.average,
-                    &new_anime.native,
-                    &new_anime.romaji,
-                    &new_anime.english,
-                ]);
-
-                match anime_result {
-                    Ok(_) => {
-                        // Download cover images and upload to S3.
-                        let mut content = Vec::new();
-                        download_image(&mut content, &entry.media.cover_image.large);
-                        let closure_id = entry.media.id.clone();
-                        let closure_ext = ext.clone();
-                        thread::spawn(move || {
-                            upload_to_s3(ImageTypes::Anime, closure_id, closure_ext, content)
-                        });
-                    }
-                    Err(error) => {
-                        error!("error saving anime={:?}. Error: {}", new_anime, error);
-                    }
+pub fn get_changes(
+    username: &str,
+    limit: i64,
+    fields: Option<&[&str]>,
+    connection: &Connection,
+) -> Option<models::ChangesResponse> {
+    let (provider_name, name) = provider::split_username(username);
+    let columns = fields.map(fields::change_columns);
+    let result =
+        repository::find_user_by_name(provider_name, name, connection).and_then(
+            |user| match user {
+                Some(user) => {
+                    repository::find_changes(user.user_id, limit, columns.as_deref(), connection)
+                        .map(Some)
                 }
+                None => Ok(None),
+            },
+        );
 
-                let start = construct_date(entry.started_at);
-                let end = construct_date(entry.completed_at);
-
-                let new_list = models::ListItem {
-                    user_id: id,
-                    anime_id: entry.media.id,
-                    user_title: entry.media.title.user_preferred,
-                    start_day: start,
-                    end_day: end,
-                    score: entry.score_raw,
-                };
-
-                let stmt = connection.prepare_cached("INSERT INTO lists (user_id, anime_id, user_title, start_day, end_day, score) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (user_id, anime_id) DO UPDATE SET user_title = excluded.user_title, start_day = excluded.start_day, end_day = excluded.end_day, score = excluded.score").unwrap();
-
-                let list_result = stmt.execute(&[
-                    &new_list.user_id,
-                    &new_list.anime_id,
-                    &new_list.user_title,
-                    &new_list.start_day,
-                    &new_list.end_day,
-                    &new_list.score,
-                ]);
-
-                if list_result.is_err() {
-                    error!(
-                        "error saving list_entry={:?}. Error: {}",
-                        new_list,
-                        list_result.expect_err("?")
-                    );
+    match result {
+        Ok(changes) => changes.map(|changes| models::ChangesResponse {
+            id: username.to_owned(),
+            changes,
+        }),
+        Err(error) => {
+            error!(
+                "error getting changes for user_name={}. Error: {}",
+                username, error
+            );
+            None
+        }
+    }
+}
+
+pub fn get_anime_history(
+    username: &str,
+    anime_id: i32,
+    connection: &Connection,
+) -> Option<models::HistoryResponse> {
+    let (provider_name, name) = provider::split_username(username);
+    let result =
+        repository::find_user_by_name(provider_name, name, connection).and_then(
+            |user| match user {
+                Some(user) => {
+                    repository::find_history(user.user_id, anime_id, connection).map(Some)
+                }
+                None => Ok(None),
+            },
+        );
+
+    match result {
+        Ok(Some(history)) if !history.is_empty() => Some(models::HistoryResponse {
+            id: username.to_owned(),
+            anime_id,
+            history,
+        }),
+        Ok(_) => None,
+        Err(error) => {
+            error!(
+                "error getting history of anime_id={} for user_name={}. Error: {}",
+                anime_id, username, error
+            );
+            None
+        }
+    }
+}
+
+// Brings back an entry that was removed from a user's list, returning whether there was one.
+pub fn restore_entry(user_id: i32, anime_id: i32, connection: &Connection) -> Option<bool> {
+    match repository::restore_list_item(user_id, anime_id, connection) {
+        Ok(restored) => Some(restored),
+        Err(error) => {
+            error!(
+                "error restoring anime_id={} for user_id={}. Error: {}",
+                anime_id, user_id, error
+            );
+            None
+        }
+    }
+}
+
+pub fn search_list(
+    username: &str,
+    search: &str,
+    limit: i64,
+    fields: Option<&[&str]>,
+    connection: &Connection,
+) -> Option<models::SearchResponse> {
+    let (provider_name, name) = provider::split_username(username);
+    let columns = fields.map(fields::search_columns);
+    match repository::search_list(
+        provider_name,
+        name,
+        search,
+        limit,
+        columns.as_deref(),
+        connection,
+    ) {
+        Ok(mut results) => {
+            for result in &mut results {
+                let highlights = &mut result.highlights;
+                for title in [
+                    &mut highlights.user_title,
+                    &mut highlights.native,
+                    &mut highlights.romaji,
+                    &mut highlights.english,
+                ] {
+                    *title = title.as_deref().map(highlight_html);
                 }
             }
+            Some(models::SearchResponse {
+                id: username.to_owned(),
+                q: search.to_owned(),
+                results,
+            })
+        }
+        Err(error) => {
+            error!(
+                "error searching list of user_name={} for q={}. Error: {}",
+                username, search, error
+            );
+            None
+        }
+    }
+}
+
+// Titles are escaped before the highlight markers become tags, so a title can't inject any HTML.
+fn highlight_html(highlight: &str) -> String {
+    html::escape(highlight)
+        .replace(repository::HIGHLIGHT_START, "<mark>")
+        .replace(repository::HIGHLIGHT_STOP, "</mark>")
+}
+
+// Gets an anime along with how it is rated here. `Ok(None)` means there is no such anime, so callers
+// can tell it apart from the database failing.
+pub fn get_anime(
+    anime_id: i32,
+    connection: &Connection,
+) -> Result<Option<models::AnimeResponse>, String> {
+    let result = repository::find_anime(anime_id, connection).and_then(|anime| match anime {
+        Some(anime) => {
+            repository::find_anime_stats(anime_id, connection).map(|stats| Some((anime, stats)))
+        }
+        None => Ok(None),
+    });
+
+    match result {
+        Ok(Some((anime, (tracked, completed, completed_average)))) => {
+            let round = |score: f64| (score * 10.0).round() / 10.0;
+            let completed_average = completed_average.map(round);
+            let average_difference = completed_average
+                .and_then(|ours| anime.average.map(|theirs| round(ours - f64::from(theirs))));
+            Ok(Some(models::AnimeResponse {
+                id: anime.anime_id,
+                mal_id: anime.mal_id,
+                native: anime.native,
+                romaji: anime.romaji,
+                english: anime.english,
+                format: anime.format,
+                episodes: anime.episodes,
+                duration: anime.duration,
+                description: anime.description,
+                cover: anime.cover_s3,
+                average: anime.average,
+                tracked,
+                completed,
+                completed_average,
+                average_difference,
+            }))
+        }
+        Ok(None) => Ok(None),
+        Err(error) => {
+            error!("error getting anime_id={}. Error: {}", anime_id, error);
+            Err(error.to_string())
+        }
+    }
+}
+
+// Searches every anime's titles, returning `None` only when the database fails.
+pub fn search_anime(
+    search: &str,
+    limit: i64,
+    connection: &Connection,
+) -> Option<models::AnimeSearchResponse> {
+    match repository::search_anime(search, limit, connection) {
+        Ok(anime) => Some(models::AnimeSearchResponse {
+            search: search.to_owned(),
+            results: anime
+                .into_iter()
+                .map(|anime| models::AnimeSummary {
+                    id: anime.anime_id,
+                    native: anime.native,
+                    romaji: anime.romaji,
+                    english: anime.english,
+                    format: anime.format,
+                    episodes: anime.episodes,
+                    cover: anime.cover_s3,
+                    average: anime.average,
+                })
+                .collect(),
+        }),
+        Err(error) => {
+            error!(
+                "error searching anime for search={}. Error: {}",
+                search, error
+            );
+            None
+        }
+    }
+}
+
+// Writes list entries that came from somewhere other than a provider sync, such as a MyAnimeList
+// import, along with the anime they belong to. Like a sync, they replace the user's list and either
+// every entry is saved or none are.
+pub fn import_entries(id: i32, entries: Vec<ProviderEntry>, connection: &Connection) -> Option<()> {
+    let start = Instant::now();
+    let result = connection.transaction().and_then(|transaction| {
+        let covers = save_entries(id, entries, &transaction)?;
+        // Counted as a sync, since it is how a local user's list changes.
+        let duration = start.elapsed().as_millis().min(i32::MAX as u128) as i32;
+        repository::record_sync(id, true, "imported", duration, &transaction)?;
+        transaction.commit()?;
+        Ok(covers)
+    });
+
+    match result {
+        Ok(covers) => {
+            // The import doesn't report uploads, so it needn't wait for them.
+            thread::spawn(move || upload_covers(covers));
+            info!("Imported entries saved for user_id={}", id);
+            Some(())
+        }
+        Err(error) => {
+            error!(
+                "error importing entries for user_id={}, changes rolled back. Error: {}",
+                id, error
+            );
+            None
+        }
+    }
+}
+
+// Makes a user's stored list match their entries, marking the rows that are no longer among them as
+// removed and recording what changed under the sync run. Returns a summary of the changes and the anime
+// whose covers should be uploaded once they are committed.
+pub fn sync_entries(
+    id: i32,
+    run_id: i32,
+    entries: Vec<ProviderEntry>,
+    connection: &dyn GenericConnection,
+) -> postgres::Result<(models::SyncSummary, Vec<(i32, String)>)> {
+    let (anime, mut list_items, covers) = to_rows(id, entries);
+    let stored = repository::find_list_items(id, connection)?;
+    changes::revive(
+        &mut list_items,
+        &repository::find_removed_list_items(id, connection)?,
+    );
+    let list_changes = changes::diff(run_id, &stored, &list_items, Utc::now());
+
+    save_rows(id, &anime, &list_items, connection)?;
+    repository::insert_all(&list_changes, connection)?;
+    Ok((changes::summarize(&list_changes), covers))
+}
+
+// Saves entries and their anime in place of a user's list the same way as a sync, but without
+// tracking changes, returning the anime whose covers should be uploaded once they are committed.
+fn save_entries(
+    id: i32,
+    entries: Vec<ProviderEntry>,
+    connection: &dyn GenericConnection,
+) -> postgres::Result<Vec<(i32, String)>> {
+    let (anime, mut list_items, covers) = to_rows(id, entries);
+    changes::revive(
+        &mut list_items,
+        &repository::find_removed_list_items(id, connection)?,
+    );
+    save_rows(id, &anime, &list_items, connection)?;
+    Ok(covers)
+}
+
+// Entries that are no longer on the list are marked removed rather than deleted, and the rest are
+// saved with one statement each.
+fn save_rows(
+    id: i32,
+    anime: &[models::Anime],
+    list_items: &[models::ListItem],
+    connection: &dyn GenericConnection,
+) -> postgres::Result<()> {
+    let anime_ids: Vec<i32> = list_items.iter().map(|item| item.anime_id).collect();
+    repository::remove_list_items_except(id, &anime_ids, connection)?;
+    repository::upsert_all(anime, connection)?;
+    repository::upsert_all(list_items, connection)?;
+    Ok(())
+}
+
+// Turns entries into the rows to store, along with the anime whose covers should be uploaded.
+#[allow(clippy::type_complexity)]
+fn to_rows(
+    id: i32,
+    entries: Vec<ProviderEntry>,
+) -> (
+    Vec<models::Anime>,
+    Vec<models::ListItem>,
+    Vec<(i32, String)>,
+) {
+    let mut anime: HashMap<i32, models::Anime> = HashMap::with_capacity(entries.len());
+    let mut list_items: HashMap<i32, models::ListItem> = HashMap::with_capacity(entries.len());
+    let mut covers = Vec::new();
+    for entry in entries {
+        let new_list = models::ListItem {
+            user_id: id,
+            anime_id: entry.media.id,
+            user_title: entry.media.user_title.clone(),
+            start_day: entry.start_day,
+            end_day: entry.end_day,
+            score: entry.score,
+            status: entry.status,
+            progress: entry.progress,
+            removed_at: None,
+        };
+
+        // An anime can show up twice, such as when two MyAnimeList entries map to it, and a
+        // statement can't upsert the same row twice.
+        if !anime.contains_key(&entry.media.id) && !entry.media.cover.is_empty() {
+            covers.push((entry.media.id, entry.media.cover.clone()));
+        }
+        anime.insert(entry.media.id, new_anime(entry.media));
+        list_items.insert(new_list.anime_id, new_list);
+    }
+
+    (
+        anime.into_values().collect(),
+        list_items.into_values().collect(),
+        covers,
+    )
+}
+
+/// A local user as found or made by `get_or_create_local_user`.
+pub enum LocalUser {
+    Existing(i32),
+    /// Made just now, along with the token that proves ownership of them.
+    Created(i32, String),
+}
+
+// Gets the id of the locally created user with this name, if there is one.
+pub fn find_local_user_id(name: &str, connection: &Connection) -> Option<i32> {
+    match repository::find_local_user(name, connection) {
+        Ok(user) => user.map(|user| user.user_id),
+        Err(error) => {
+            error!("error finding local user_name={}. Error: {}", name, error);
+            None
+        }
+    }
+}
+
+// Gets the id of the locally created user with this name, creating them along with their token if
+// they don't exist yet.
+pub fn get_or_create_local_user(name: &str, connection: &Connection) -> Option<LocalUser> {
+    match repository::find_local_user(name, connection) {
+        Ok(Some(user)) => return Some(LocalUser::Existing(user.user_id)),
+        Ok(None) => (),
+        Err(error) => {
+            error!("error finding local user_name={}. Error: {}", name, error);
+            return None;
+        }
+    }
+
+    let new_user = models::User {
+        user_id: next_local_id(connection)?,
+        name: name.to_owned(),
+        avatar_s3: String::new(),
+        avatar_anilist: String::new(),
+        provider: "local".to_owned(),
+        external_id: None,
+    };
+    let token = auth::new_token()?;
+
+    let result = connection.transaction().and_then(|transaction| {
+        repository::upsert(&new_user, &transaction)?;
+        repository::set_user_token(new_user.user_id, &token, &transaction)?;
+        transaction.commit()
+    });
+    match result {
+        Ok(_) => Some(LocalUser::Created(new_user.user_id, token)),
+        Err(error) => {
+            error!("error creating local user_name={}. Error: {}", name, error);
+            None
+        }
+    }
+}
+
+// Whether a request's token proves it comes from the owner of a user.
+pub fn owns_user(user_id: i32, token: &str, connection: &Connection) -> bool {
+    match repository::check_user_token(user_id, token, connection) {
+        Ok(owned) => owned,
+        Err(error) => {
+            error!(
+                "error checking the token of user_id={}. Error: {}",
+                user_id, error
+            );
+            false
         }
     }
-    info!("Database updated for user_id={}", id);
 }
 
-fn upload_to_s3(prefix: ImageTypes, id: i32, ext: String, content: Vec<u8>) {
+fn next_local_id(connection: &Connection) -> Option<i32> {
+    match repository::next_local_id(connection) {
+        Ok(user_id) => Some(user_id),
+        Err(error) => {
+            error!("error allocating a local user id. Error: {}", error);
+            None
+        }
+    }
+}
+
+fn new_anime(media: ProviderMedia) -> models::Anime {
+    let ext = get_ext(&media.cover);
+
+    let description_text = description::plain_text(&media.description);
+    models::Anime {
+        anime_id: media.id,
+        description: description::sanitize(&media.description),
+        description_summary: Some(description::summary(&description_text)),
+        description_text: Some(description_text),
+        cover_s3: format!(
+            "https://s3.amazonaws.com/anihistory-images/assets/images/anime_{}.{}",
+            media.id, ext
+        ),
+        cover_anilist: media.cover,
+        average: media.average,
+        native: media.native,
+        romaji: media.romaji,
+        english: media.english,
+        format: media.format,
+        episodes: media.episodes,
+        duration: media.duration,
+        mal_id: media.mal_id,
+    }
+}
+
+// Download cover images and upload to S3, returning how many uploads succeeded once they are all
+// done. Covers that can't be downloaded are skipped.
+fn upload_covers(covers: Vec<(i32, String)>) -> i32 {
+    let mut uploads = Vec::with_capacity(covers.len());
+    for (id, cover) in covers {
+        let ext = get_ext(&cover);
+        let content = match download_image(&cover) {
+            Ok(content) => content,
+            Err(error) => {
+                error!(
+                    "error downloading cover of anime_id={}. Error: {}",
+                    id, error
+                );
+                continue;
+            }
+        };
+        uploads.push(thread::spawn(move || {
+            upload_to_s3(ImageTypes::Anime, id, ext, content)
+        }));
+    }
+    uploads
+        .into_iter()
+        .map(|upload| upload.join().unwrap_or(false))
+        .filter(|&uploaded| uploaded)
+        .count() as i32
+}
+
+// Returns whether the upload succeeded.
+fn upload_to_s3(prefix: ImageTypes, id: i32, ext: String, content: Vec<u8>) -> bool {
     let image_prefix: String;
     match prefix {
         ImageTypes::Anime => image_prefix = "anime".to_owned(),
@@ -338,38 +912,35 @@ fn upload_to_s3(prefix: ImageTypes, id: i32, ext: String, content: Vec<u8>) {
     };
 
     match client.put_object(put_request).sync() {
-        Ok(_) => (),
+        Ok(_) => true,
         Err(error) => {
             error!(
                 "error uploading assets/images/{}_{}.{} to S3. Error: {}",
                 image_prefix, id, ext, error
             );
+            false
         }
     }
 }
 
-fn construct_date(date: anilist_models::Date) -> Option<NaiveDate> {
-    match date.year {
-        Some(year) => match date.month {
-            Some(month) => match date.day {
-                Some(day) => Some(NaiveDate::from_ymd(year, month as u32, day as u32)),
-                None => None,
-            },
-            None => None,
-        },
-        None => None,
-    }
-}
-
-fn download_image(content: &mut Vec<u8>, url: &String) {
-    let mut resp = get(url).unwrap();
-    resp.read_to_end(content).unwrap();
+fn download_image(url: &str) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    get(url)
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| error.to_string())?
+        .read_to_end(&mut content)
+        .map_err(|error| error.to_string())?;
+    Ok(content)
 }
 
-fn get_ext(url: &String) -> String {
-    let link_parts: Vec<&str> = url.split('/').collect();
-    let splitted: Vec<&str> = link_parts[link_parts.len() - 1].split(".").collect();
-    splitted[1].to_owned()
+// Some providers add a query string to their image links, and local users have no image at all.
+fn get_ext(url: &str) -> String {
+    let path = url.split('?').next().unwrap_or_default();
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    match file_name.rsplit('.').next() {
+        Some(ext) if ext != file_name => ext.to_owned(),
+        _ => "png".to_owned(),
+    }
 }
 
 fn naive_mime(ext: &String) -> String {
@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use ammonia::{Builder, UrlRelative};
+
+// The formatting provider descriptions use. Any other tag, such as a script or an image, is dropped
+// along with its attributes.
+static ALLOWED_TAGS: [&str; 10] = ["a", "b", "br", "em", "i", "li", "ol", "p", "strong", "ul"];
+static LINK_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+// What the tags that break lines become in plain text. Paragraphs and lists are set apart by a blank
+// line and list items start their own line.
+static LINE_BREAKS: [(&str, &str); 9] = [
+    ("<br>", "\n"),
+    ("<p>", "\n\n"),
+    ("</p>", "\n\n"),
+    ("<ol>", "\n\n"),
+    ("</ol>", "\n\n"),
+    ("<ul>", "\n\n"),
+    ("</ul>", "\n\n"),
+    ("<li>", "\n"),
+    ("</li>", ""),
+];
+static SUMMARY_LENGTH: usize = 200;
+
+/// Which form of their descriptions list entries are sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DescriptionFormat {
+    #[default]
+    Html,
+    Text,
+    Summary,
+    Omitted,
+}
+
+impl DescriptionFormat {
+    pub fn from_param(param: Option<String>) -> Result<DescriptionFormat, String> {
+        match param.as_deref() {
+            None | Some("html") => Ok(DescriptionFormat::Html),
+            Some("text") => Ok(DescriptionFormat::Text),
+            Some("summary") => Ok(DescriptionFormat::Summary),
+            Some("none") => Ok(DescriptionFormat::Omitted),
+            Some(param) => Err(format!(
+                "Unknown description {}, expected html, text, summary or none",
+                param
+            )),
+        }
+    }
+}
+
+/// Strips a provider's description down to `ALLOWED_TAGS`, so clients can show it as HTML without
+/// trusting the provider. Links are kept when they are http, https or mailto.
+pub fn sanitize(html: &str) -> String {
+    Builder::default()
+        .tags(ALLOWED_TAGS.iter().cloned().collect())
+        .url_schemes(LINK_SCHEMES.iter().cloned().collect())
+        .url_relative(UrlRelative::Deny)
+        .clean(html)
+        .to_string()
+}
+
+/// A description without any markup, keeping its line breaks but no more than one blank line in a
+/// row.
+pub fn plain_text(html: &str) -> String {
+    // Cleaning leaves the tags that break lines without attributes and in one form, and drops the
+    // rest.
+    let mut text = Builder::empty()
+        .tags(["br", "li", "ol", "p", "ul"].iter().cloned().collect())
+        .clean_content_tags(["script", "style"].iter().cloned().collect())
+        .clean(html)
+        .to_string();
+    for (tag, line_break) in LINE_BREAKS.iter() {
+        text = text.replace(tag, line_break);
+    }
+    let text = text
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if !line.is_empty() || lines.last().map_or(false, |last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n").trim_end().to_owned()
+}
+
+/// The start of a plain text description on one line, cut at a word and marked with an ellipsis
+/// when it runs past `SUMMARY_LENGTH` characters.
+pub fn summary(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if text.chars().count() <= SUMMARY_LENGTH {
+        return text;
+    }
+
+    let cut: String = text.chars().take(SUMMARY_LENGTH).collect();
+    let cut = match cut.rfind(' ') {
+        Some(index) => &cut[..index],
+        None => &cut,
+    };
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_ascii_punctuation())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_description_params() {
+        assert_eq!(
+            DescriptionFormat::from_param(None),
+            Ok(DescriptionFormat::Html)
+        );
+        assert_eq!(
+            DescriptionFormat::from_param(Some("summary".to_owned())),
+            Ok(DescriptionFormat::Summary)
+        );
+        assert!(DescriptionFormat::from_param(Some("markdown".to_owned())).is_err());
+    }
+
+    #[test]
+    fn sanitize_keeps_formatting_and_drops_the_rest() {
+        assert_eq!(
+            sanitize("<p onclick=\"x()\"><b>Bold</b><script>x()</script><img src=\"a.png\"></p>"),
+            "<p><b>Bold</b></p>"
+        );
+    }
+
+    #[test]
+    fn sanitize_keeps_only_web_and_mail_links() {
+        assert_eq!(
+            sanitize("<a href=\"https://anilist.co\">a</a>"),
+            "<a href=\"https://anilist.co\" rel=\"noopener noreferrer\">a</a>"
+        );
+        assert_eq!(
+            sanitize("<a href=\"mailto:a@b.c\">a</a>"),
+            "<a href=\"mailto:a@b.c\" rel=\"noopener noreferrer\">a</a>"
+        );
+        for href in &[
+            "javascript:alert(1)",
+            "ftp://a.b/c",
+            "data:text/html,x",
+            "/relative",
+        ] {
+            assert_eq!(
+                sanitize(&format!("<a href=\"{}\">a</a>", href)),
+                "<a rel=\"noopener noreferrer\">a</a>"
+            );
+        }
+    }
+
+    #[test]
+    fn plain_text_keeps_line_breaks() {
+        assert_eq!(
+            plain_text("First<br><br><br>Second<br/>Third<BR />"),
+            "First\n\nSecond\nThird"
+        );
+    }
+
+    #[test]
+    fn plain_text_breaks_lines_at_blocks() {
+        assert_eq!(plain_text("<p>One</p><p>Two</p>"), "One\n\nTwo");
+        assert_eq!(
+            plain_text("Cast:<ul><li>A</li><li>B</li></ul>End"),
+            "Cast:\n\nA\nB\n\nEnd"
+        );
+    }
+
+    #[test]
+    fn plain_text_drops_markup_and_decodes_entities() {
+        assert_eq!(
+            plain_text("<i>Tom &amp; Jerry</i> &lt;3<script>alert(1)</script>"),
+            "Tom & Jerry <3"
+        );
+    }
+
+    #[test]
+    fn short_summaries_are_whole() {
+        assert_eq!(summary("A short\n\ndescription."), "A short description.");
+    }
+
+    #[test]
+    fn long_summaries_are_cut_at_a_word() {
+        let text = "word, ".repeat(50);
+        let cut = summary(&text);
+        assert!(cut.ends_with("word…"));
+        assert!(cut.chars().count() <= SUMMARY_LENGTH + 1);
+    }
+}
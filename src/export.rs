@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::models;
+use crate::titles::TitleLanguage;
+use csv::WriterBuilder;
+use std::io::{self, Read};
+use std::mem;
+
+// Column names are part of the export format, so only ever append to this list.
+pub static COLUMNS: [&str; 22] = [
+    "id",
+    "user_title",
+    "romaji",
+    "english",
+    "native",
+    "format",
+    "episodes",
+    "duration",
+    "status",
+    "progress",
+    "score",
+    "average",
+    "start_day",
+    "end_day",
+    "cover",
+    "description",
+    "user",
+    "avatar",
+    "cover_anilist",
+    "anilist_url",
+    "mal_id",
+    "display_title",
+];
+
+pub enum Delimiter {
+    Comma,
+    Tab,
+}
+
+/// Parses a comma separated `columns` query parameter, keeping the requested order. Every column
+/// is exported when nothing is requested.
+pub fn parse_columns(param: Option<String>) -> Result<Vec<&'static str>, String> {
+    let param = match param {
+        Some(param) if !param.trim().is_empty() => param,
+        _ => return Ok(COLUMNS.to_vec()),
+    };
+
+    let mut columns = Vec::new();
+    for requested in param.split(',').map(|c| c.trim()).filter(|c| !c.is_empty()) {
+        match COLUMNS.iter().find(|&&column| column == requested) {
+            Some(column) => columns.push(*column),
+            None => {
+                return Err(format!(
+                    "Unknown column {}. Available columns are {}",
+                    requested,
+                    COLUMNS.join(",")
+                ))
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// A list rendered as CSV or TSV a row at a time as it is read, so an export is never held in
+/// memory as a whole.
+pub struct Export {
+    items: std::vec::IntoIter<models::ListItemMap>,
+    columns: Vec<&'static str>,
+    delimiter: u8,
+    title: TitleLanguage,
+    // The rendered row not yet read, starting at `position`.
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Export {
+    /// An export of `items` with the given columns, which have to come from `parse_columns`.
+    pub fn new(
+        items: Vec<models::ListItemMap>,
+        columns: Vec<&'static str>,
+        delimiter: Delimiter,
+        title: TitleLanguage,
+    ) -> io::Result<Export> {
+        let delimiter = match delimiter {
+            Delimiter::Comma => b',',
+            Delimiter::Tab => b'\t',
+        };
+        let mut export = Export {
+            items: items.into_iter(),
+            columns,
+            delimiter,
+            title,
+            buffer: Vec::new(),
+            position: 0,
+        };
+        let header = export.columns.clone();
+        export.write_row(header)?;
+        Ok(export)
+    }
+
+    // Replaces the buffer with one row, reusing its allocation.
+    fn write_row<I, T>(&mut self, record: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        self.buffer.clear();
+        self.position = 0;
+        let mut writer = WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .from_writer(mem::take(&mut self.buffer));
+        writer.write_record(record)?;
+        self.buffer = writer.into_inner().map_err(|error| error.into_error())?;
+        Ok(())
+    }
+}
+
+impl Read for Export {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            let item = match self.items.next() {
+                Some(item) => item,
+                None => return Ok(0),
+            };
+            let title = self.title;
+            let record: Vec<String> = self
+                .columns
+                .iter()
+                .map(|column| value(&item, column, title))
+                .collect();
+            self.write_row(record)?;
+        }
+
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+fn value(item: &models::ListItemMap, column: &str, title: TitleLanguage) -> String {
+    let list_item = &item.list_item;
+    let anime = &item.anime;
+    match column {
+        "id" => anime.anime_id.to_string(),
+        "user_title" => optional(&list_item.user_title),
+        "romaji" => optional(&anime.romaji),
+        "english" => optional(&anime.english),
+        "native" => optional(&anime.native),
+        "format" => optional(&anime.format),
+        "episodes" => optional(&anime.episodes),
+        "duration" => optional(&anime.duration),
+        "status" => optional(&list_item.status),
+        "progress" => optional(&list_item.progress),
+        "score" => optional(&list_item.score),
+        "average" => optional(&anime.average),
+        "start_day" => optional(&list_item.start_day),
+        "end_day" => optional(&list_item.end_day),
+        "cover" => anime.cover_s3.clone(),
+        "description" => anime.description.clone(),
+        "user" => item.user.name.clone(),
+        "avatar" => item.user.avatar_s3.clone(),
+        "cover_anilist" => anime.cover_anilist.clone(),
+        "anilist_url" => format!("https://anilist.co/anime/{}", anime.anime_id),
+        "mal_id" => optional(&anime.mal_id),
+        "display_title" => title.pick(
+            list_item.user_title.as_deref(),
+            anime.romaji.as_deref(),
+            anime.english.as_deref(),
+            anime.native.as_deref(),
+        ),
+        _ => unreachable!("unknown export column {}", column),
+    }
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn item(anime_id: i32, user_title: &str) -> models::ListItemMap {
+        models::ListItemMap {
+            user: models::User {
+                user_id: 1,
+                name: "Foo".to_owned(),
+                avatar_s3: String::new(),
+                avatar_anilist: String::new(),
+                provider: "anilist".to_owned(),
+                external_id: Some(1),
+            },
+            anime: models::Anime {
+                anime_id,
+                description: "<p>A show</p>".to_owned(),
+                cover_s3: String::new(),
+                cover_anilist: String::new(),
+                average: None,
+                native: None,
+                romaji: Some("Romaji".to_owned()),
+                english: None,
+                format: None,
+                episodes: Some(12),
+                duration: None,
+                mal_id: None,
+                description_text: None,
+                description_summary: None,
+            },
+            list_item: models::ListItem {
+                user_id: 1,
+                anime_id,
+                user_title: Some(user_title.to_owned()),
+                start_day: NaiveDate::from_ymd_opt(2020, 1, 2),
+                end_day: None,
+                score: Some(80),
+                status: Some("COMPLETED".to_owned()),
+                progress: None,
+                removed_at: None,
+            },
+        }
+    }
+
+    fn read(export: Export) -> String {
+        let mut text = String::new();
+        io::BufReader::with_capacity(7, export)
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn exports_every_column_by_default() {
+        assert_eq!(parse_columns(None).unwrap(), COLUMNS.to_vec());
+        assert_eq!(
+            parse_columns(Some(" ".to_owned())).unwrap(),
+            COLUMNS.to_vec()
+        );
+    }
+
+    #[test]
+    fn keeps_the_requested_order() {
+        let columns = parse_columns(Some("score, id,,start_day".to_owned())).unwrap();
+        assert_eq!(columns, vec!["score", "id", "start_day"]);
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        let error = parse_columns(Some("id,rating".to_owned())).unwrap_err();
+        assert!(error.starts_with("Unknown column rating."));
+    }
+
+    #[test]
+    fn writes_a_row_per_item() {
+        let items = vec![item(1, "Show, with a comma"), item(2, "Other")];
+        let columns = parse_columns(Some("id,user_title,score,start_day,end_day".to_owned()));
+        let export = Export::new(
+            items,
+            columns.unwrap(),
+            Delimiter::Comma,
+            TitleLanguage::User,
+        );
+
+        assert_eq!(
+            read(export.unwrap()),
+            "id,user_title,score,start_day,end_day\n\
+             1,\"Show, with a comma\",80,2020-01-02,\n\
+             2,Other,80,2020-01-02,\n"
+        );
+    }
+
+    #[test]
+    fn writes_tabs_for_tsv() {
+        let export = Export::new(
+            vec![item(1, "Show")],
+            vec!["id", "display_title"],
+            Delimiter::Tab,
+            TitleLanguage::Romaji,
+        );
+
+        assert_eq!(read(export.unwrap()), "id\tdisplay_title\n1\tRomaji\n");
+    }
+}
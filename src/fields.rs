@@ -0,0 +1,324 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::description::DescriptionFormat;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+/// The fields of a list entry, as `ResponseItem` serializes them.
+pub static LIST_FIELDS: [&str; 18] = [
+    "display_title",
+    "user_title",
+    "start_day",
+    "end_day",
+    "score",
+    "status",
+    "progress",
+    "average",
+    "native",
+    "romaji",
+    "english",
+    "format",
+    "episodes",
+    "duration",
+    "description",
+    "cover",
+    "id",
+    "removed_at",
+];
+
+pub static CHANGE_FIELDS: [&str; 10] = [
+    "id",
+    "user_title",
+    "native",
+    "romaji",
+    "english",
+    "changed_at",
+    "change",
+    "field",
+    "old_value",
+    "new_value",
+];
+
+pub static SEARCH_FIELDS: [&str; 12] = [
+    "id",
+    "user_title",
+    "native",
+    "romaji",
+    "english",
+    "status",
+    "score",
+    "start_day",
+    "end_day",
+    "cover",
+    "rank",
+    "highlights",
+];
+
+/// The fields asked for in a comma separated `fields` parameter, or `None` for all of them.
+pub fn parse(
+    param: Option<String>,
+    available: &[&'static str],
+) -> Result<Option<Vec<&'static str>>, String> {
+    let param = match param {
+        Some(param) if !param.trim().is_empty() => param,
+        _ => return Ok(None),
+    };
+
+    let mut fields = Vec::new();
+    for requested in param.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+        match available.iter().find(|&&field| field == requested) {
+            Some(field) => fields.push(*field),
+            None => {
+                return Err(format!(
+                    "Unknown field {}. Available fields are {}",
+                    requested,
+                    available.join(",")
+                ))
+            }
+        }
+    }
+    Ok(Some(fields))
+}
+
+/// The anime and list columns needed to fill in the given list entry fields. Keys are always read,
+/// so `id` needs nothing more.
+pub fn list_columns(fields: &[&str], description: DescriptionFormat) -> Vec<&'static str> {
+    columns_for(fields, |field| match field {
+        "display_title" => &["user_title", "romaji", "english", "native"],
+        "user_title" => &["user_title"],
+        "start_day" => &["start_day"],
+        "end_day" => &["end_day"],
+        "score" => &["score"],
+        "status" => &["status"],
+        "progress" => &["progress"],
+        "average" => &["average"],
+        "native" => &["native"],
+        "romaji" => &["romaji"],
+        "english" => &["english"],
+        "format" => &["format"],
+        "episodes" => &["episodes"],
+        "duration" => &["duration"],
+        // The stored plain text and summary are missing until the server fills them in, so the
+        // HTML is read as well to make them from.
+        "description" => match description {
+            DescriptionFormat::Html => &["description"],
+            DescriptionFormat::Text => &["description", "description_text"],
+            DescriptionFormat::Summary => &["description", "description_summary"],
+            DescriptionFormat::Omitted => &[],
+        },
+        "cover" => &["cover_s3"],
+        "removed_at" => &["removed_at"],
+        _ => &[],
+    })
+}
+
+/// The columns `repository::find_changes` needs to fill in the given change fields. The anime id is
+/// always read.
+pub fn change_columns(fields: &[&str]) -> Vec<&'static str> {
+    columns_for(fields, |field| match field {
+        "user_title" => &["user_title"],
+        "native" => &["native"],
+        "romaji" => &["romaji"],
+        "english" => &["english"],
+        "changed_at" => &["changed_at"],
+        "change" => &["change"],
+        "field" => &["field"],
+        "old_value" => &["old_value"],
+        "new_value" => &["new_value"],
+        _ => &[],
+    })
+}
+
+/// The columns `repository::search_list` needs to fill in the given search result fields. The
+/// anime id is always read.
+pub fn search_columns(fields: &[&str]) -> Vec<&'static str> {
+    columns_for(fields, |field| match field {
+        "user_title" => &["user_title"],
+        "native" => &["native"],
+        "romaji" => &["romaji"],
+        "english" => &["english"],
+        "status" => &["status"],
+        "score" => &["score"],
+        "start_day" => &["start_day"],
+        "end_day" => &["end_day"],
+        "cover" => &["cover_s3"],
+        "rank" => &["rank"],
+        "highlights" => &[
+            "user_title_highlight",
+            "native_highlight",
+            "romaji_highlight",
+            "english_highlight",
+        ],
+        _ => &[],
+    })
+}
+
+fn columns_for(
+    fields: &[&str],
+    needed: impl Fn(&str) -> &'static [&'static str],
+) -> Vec<&'static str> {
+    let mut columns = Vec::new();
+    for field in fields {
+        for column in needed(field) {
+            if !columns.contains(column) {
+                columns.push(*column);
+            }
+        }
+    }
+    columns
+}
+
+/// A response trimmed to the requested fields, or the whole of it when none were asked for.
+pub enum Selected<T> {
+    All(T),
+    Some(Value),
+}
+
+impl<T: Serialize> Serialize for Selected<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Selected::All(response) => response.serialize(serializer),
+            Selected::Some(value) => value.serialize(serializer),
+        }
+    }
+}
+
+/// Keeps only the given fields of each object in the array at `path`. Whole responses are
+/// serialized as they are, while trimmed ones go through `Value` and so have their keys sorted.
+pub fn select<T: Serialize>(
+    response: T,
+    path: &[&str],
+    fields: Option<&[&str]>,
+) -> serde_json::Result<Selected<T>> {
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(Selected::All(response)),
+    };
+
+    let mut value = serde_json::to_value(response)?;
+    let items = path
+        .iter()
+        .try_fold(&mut value, |value, key| value.get_mut(key));
+    if let Some(Value::Array(items)) = items {
+        for item in items {
+            if let Value::Object(item) = item {
+                item.retain(|key, _| fields.contains(&key.as_str()));
+            }
+        }
+    }
+    Ok(Selected::Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::Serialize;
+
+    #[derive(Serialize)]
+    struct Item {
+        id: i32,
+        score: Option<i16>,
+        title: &'static str,
+    }
+
+    #[derive(Serialize)]
+    struct Response {
+        id: &'static str,
+        list: Vec<Item>,
+    }
+
+    fn response() -> Response {
+        Response {
+            id: "Foo",
+            list: vec![
+                Item {
+                    id: 1,
+                    score: Some(80),
+                    title: "A",
+                },
+                Item {
+                    id: 2,
+                    score: None,
+                    title: "B",
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn parse_reads_known_fields() {
+        assert_eq!(
+            parse(Some(" id, score ,,".to_owned()), &LIST_FIELDS),
+            Ok(Some(vec!["id", "score"]))
+        );
+    }
+
+    #[test]
+    fn parse_treats_missing_or_blank_params_as_everything() {
+        assert_eq!(parse(None, &LIST_FIELDS), Ok(None));
+        assert_eq!(parse(Some(" ".to_owned()), &LIST_FIELDS), Ok(None));
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fields() {
+        let error = parse(Some("id,rank".to_owned()), &LIST_FIELDS).unwrap_err();
+        assert!(error.starts_with("Unknown field rank."));
+    }
+
+    #[test]
+    fn list_columns_reads_what_fields_need_once() {
+        assert_eq!(
+            list_columns(
+                &["id", "display_title", "native", "cover"],
+                DescriptionFormat::Html
+            ),
+            vec!["user_title", "romaji", "english", "native", "cover_s3"]
+        );
+    }
+
+    #[test]
+    fn list_columns_follow_the_description_format() {
+        assert_eq!(
+            list_columns(&["description"], DescriptionFormat::Summary),
+            vec!["description", "description_summary"]
+        );
+        assert!(list_columns(&["description"], DescriptionFormat::Omitted).is_empty());
+    }
+
+    #[test]
+    fn every_field_can_be_read() {
+        for field in LIST_FIELDS.iter().filter(|&&field| field != "id") {
+            assert!(!list_columns(&[field], DescriptionFormat::Html).is_empty());
+        }
+        for field in CHANGE_FIELDS.iter().filter(|&&field| field != "id") {
+            assert!(!change_columns(&[field]).is_empty());
+        }
+        for field in SEARCH_FIELDS.iter().filter(|&&field| field != "id") {
+            assert!(!search_columns(&[field]).is_empty());
+        }
+    }
+
+    #[test]
+    fn select_keeps_only_the_given_fields() {
+        let selected = select(response(), &["list"], Some(&["id", "score"])).unwrap();
+        assert_eq!(
+            serde_json::to_string(&selected).unwrap(),
+            r#"{"id":"Foo","list":[{"id":1,"score":80},{"id":2,"score":null}]}"#
+        );
+    }
+
+    #[test]
+    fn select_leaves_whole_responses_alone() {
+        let selected = select(response(), &["list"], None).unwrap();
+        assert_eq!(
+            serde_json::to_string(&selected).unwrap(),
+            serde_json::to_string(&response()).unwrap()
+        );
+    }
+}
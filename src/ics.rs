@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{models, timeline};
+use chrono::{Duration, NaiveDate, Utc};
+
+pub enum MissingDates {
+    /// Leave out entries without both a start and an end date.
+    Skip,
+    /// Turn entries with only one date into a single day event on that date.
+    SingleDay,
+}
+
+impl MissingDates {
+    pub fn from_param(param: Option<String>) -> MissingDates {
+        match param.as_deref() {
+            Some("single") => MissingDates::SingleDay,
+            _ => MissingDates::Skip,
+        }
+    }
+}
+
+/// Renders a user's list as an iCalendar file with one all day VEVENT per entry, spanning its
+/// start and end days.
+pub fn render(list: &models::ResponseList, missing: &MissingDates) -> String {
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_owned(),
+        "VERSION:2.0".to_owned(),
+        "PRODID:-//anihistory.moe//anihistory_server//EN".to_owned(),
+        "CALSCALE:GREGORIAN".to_owned(),
+        format!(
+            "X-WR-CALNAME:{}",
+            escape(&format!("{}'s anime history", list.id))
+        ),
+    ];
+
+    for item in list.list.iter() {
+        let (start, end) = match (item.start_day, item.end_day, missing) {
+            (Some(start), Some(end), _) => (start, end.max(start)),
+            (Some(day), None, MissingDates::SingleDay)
+            | (None, Some(day), MissingDates::SingleDay) => (day, day),
+            _ => continue,
+        };
+
+        let url = format!("https://anilist.co/anime/{}", item.id);
+        let mut description = String::new();
+        if let Some(score) = item.score.filter(|score| *score > 0) {
+            description.push_str(&format!("Score: {}/100\n", score));
+        }
+        description.push_str(&url);
+
+        lines.push("BEGIN:VEVENT".to_owned());
+        lines.push(format!(
+            "UID:{}-{}@anihistory.moe",
+            list.id.to_lowercase(),
+            item.id
+        ));
+        lines.push(format!("DTSTAMP:{}", stamp));
+        lines.push(format!("DTSTART;VALUE=DATE:{}", ics_date(start)));
+        // All day events end on the day after the last one they cover.
+        lines.push(format!(
+            "DTEND;VALUE=DATE:{}",
+            ics_date(end + Duration::days(1))
+        ));
+        lines.push(format!("SUMMARY:{}", escape(timeline::title(item))));
+        lines.push(format!("DESCRIPTION:{}", escape(&description)));
+        lines.push(format!("URL:{}", url));
+        lines.push("TRANSP:TRANSPARENT".to_owned());
+        lines.push("END:VEVENT".to_owned());
+    }
+
+    lines.push("END:VCALENDAR".to_owned());
+
+    let mut calendar = String::new();
+    for line in lines {
+        calendar.push_str(&fold(&line));
+        calendar.push_str("\r\n");
+    }
+    calendar
+}
+
+fn ics_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Lines longer than 75 octets have to be folded onto continuation lines starting with a space,
+// without splitting a multi-byte character.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len());
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_text_values() {
+        assert_eq!(escape("a\\b;c,d\ne"), r"a\\b\;c\,d\ne");
+    }
+
+    #[test]
+    fn leaves_short_lines_alone() {
+        let line = "x".repeat(75);
+        assert_eq!(fold(&line), line);
+    }
+
+    #[test]
+    fn folds_long_lines_at_75_octets() {
+        let folded = fold(&"x".repeat(160));
+        let lines: Vec<&str> = folded.split("\r\n").collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1..].iter().all(|line| line.starts_with(' ')));
+        assert_eq!(folded.replace("\r\n ", ""), "x".repeat(160));
+    }
+
+    #[test]
+    fn folds_without_splitting_characters() {
+        let folded = fold(&"あ".repeat(40));
+
+        for line in folded.split("\r\n") {
+            assert!(line.len() <= 75);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), "あ".repeat(40));
+    }
+}
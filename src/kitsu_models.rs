@@ -0,0 +1,143 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use serde_derive::{Deserialize, Serialize};
+
+// User Structs
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserResponse {
+    pub data: Vec<User>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: String,
+    pub attributes: UserAttributes,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserAttributes {
+    pub name: String,
+    pub avatar: Option<Image>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Image {
+    pub large: Option<String>,
+}
+
+// Library Structs
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryResponse {
+    pub data: Vec<LibraryEntry>,
+    #[serde(default)]
+    pub included: Vec<Included>,
+    pub links: Links,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Links {
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryEntry {
+    pub id: String,
+    pub attributes: LibraryEntryAttributes,
+    pub relationships: LibraryEntryRelationships,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryEntryAttributes {
+    pub status: String,
+    pub progress: Option<i32>,
+    #[serde(rename = "ratingTwenty")]
+    pub rating_twenty: Option<i16>,
+    #[serde(rename = "startedAt")]
+    pub started_at: Option<String>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryEntryRelationships {
+    pub anime: Relationship,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Relationship {
+    pub data: Option<ResourceIdentifier>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManyRelationship {
+    #[serde(default)]
+    pub data: Vec<ResourceIdentifier>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ResourceIdentifier {
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum Included {
+    #[serde(rename = "anime")]
+    Anime {
+        id: String,
+        attributes: AnimeAttributes,
+        relationships: Option<AnimeRelationships>,
+    },
+    #[serde(rename = "mappings")]
+    Mapping {
+        id: String,
+        attributes: MappingAttributes,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AnimeAttributes {
+    #[serde(rename = "canonicalTitle")]
+    pub canonical_title: Option<String>,
+    pub titles: Titles,
+    pub synopsis: Option<String>,
+    #[serde(rename = "posterImage")]
+    pub poster_image: Option<Image>,
+    #[serde(rename = "averageRating")]
+    pub average_rating: Option<String>,
+    pub subtype: Option<String>,
+    #[serde(rename = "episodeCount")]
+    pub episode_count: Option<i32>,
+    #[serde(rename = "episodeLength")]
+    pub episode_length: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Titles {
+    pub en: Option<String>,
+    pub en_jp: Option<String>,
+    pub ja_jp: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AnimeRelationships {
+    pub mappings: Option<ManyRelationship>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MappingAttributes {
+    #[serde(rename = "externalSite")]
+    pub external_site: String,
+    #[serde(rename = "externalId")]
+    pub external_id: String,
+}
@@ -0,0 +1,239 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::provider::{ListProvider, ProviderEntry, ProviderMedia, ProviderUser};
+use crate::{anilist_query, kitsu_models};
+use chrono::NaiveDate;
+use log::{error, info};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::ACCEPT;
+use serde_json::from_str;
+use std::collections::HashMap;
+
+pub struct Kitsu;
+
+impl ListProvider for Kitsu {
+    fn name(&self) -> &'static str {
+        "kitsu"
+    }
+
+    fn get_user(&self, username: &str) -> Option<ProviderUser> {
+        let client = Client::new();
+        let request = client
+            .get(&format!("{}/users", KITSU_URL))
+            .query(&[("filter[name]", username), ("fields[users]", "name,avatar")]);
+        let json: kitsu_models::UserResponse = send(request)?;
+
+        match json.data.into_iter().next() {
+            Some(user) => Some(ProviderUser {
+                id: user.id.parse().ok()?,
+                name: user.attributes.name,
+                avatar: user
+                    .attributes
+                    .avatar
+                    .and_then(|avatar| avatar.large)
+                    .unwrap_or_default(),
+            }),
+            None => {
+                error!("user_name={} was not found in kitsu", username);
+                None
+            }
+        }
+    }
+
+    fn get_entries(&self, user_id: i32) -> Option<Vec<ProviderEntry>> {
+        let client = Client::new();
+        let mut library = Vec::new();
+        let mut anime = HashMap::new();
+        let mut mappings = HashMap::new();
+
+        let mut request = Some(
+            client
+                .get(&format!("{}/library-entries", KITSU_URL))
+                .query(&[
+                    ("filter[userId]", user_id.to_string().as_ref()),
+                    ("filter[kind]", "anime"),
+                    ("filter[status]", "current,completed"),
+                    ("include", "anime,anime.mappings"),
+                    (
+                        "fields[libraryEntries]",
+                        "status,progress,ratingTwenty,startedAt,finishedAt,anime",
+                    ),
+                    ("fields[anime]", ANIME_FIELDS),
+                    ("fields[mappings]", "externalSite,externalId"),
+                    ("page[limit]", "500"),
+                ]),
+        );
+
+        // Follow the JSON:API next links until the whole library has been read.
+        while let Some(page) = request {
+            let json: kitsu_models::LibraryResponse = send(page)?;
+
+            for included in json.included {
+                match included {
+                    kitsu_models::Included::Anime {
+                        id,
+                        attributes,
+                        relationships,
+                    } => {
+                        anime.insert(id, (attributes, relationships));
+                    }
+                    kitsu_models::Included::Mapping { id, attributes } => {
+                        mappings.insert(id, attributes);
+                    }
+                    kitsu_models::Included::Other => (),
+                }
+            }
+            library.extend(json.data);
+            request = json.links.next.map(|next| client.get(&next));
+        }
+
+        // Look for each anime's AniList and MyAnimeList ids among its mappings.
+        let mut anime_ids = HashMap::new();
+        for (kitsu_id, (_, relationships)) in anime.iter() {
+            let mut anilist_id = None;
+            let mut mal_id = None;
+            let mapping_ids = relationships
+                .as_ref()
+                .and_then(|r| r.mappings.as_ref())
+                .map(|m| m.data.clone())
+                .unwrap_or_default();
+            for mapping in mapping_ids.iter().filter_map(|m| mappings.get(&m.id)) {
+                match mapping.external_site.as_ref() {
+                    "anilist/anime" => anilist_id = mapping.external_id.parse::<i32>().ok(),
+                    "myanimelist/anime" => mal_id = mapping.external_id.parse::<i32>().ok(),
+                    _ => (),
+                }
+            }
+            anime_ids.insert(kitsu_id.clone(), (anilist_id, mal_id));
+        }
+
+        // Prefer AniList's own data for anything that can be found through its MyAnimeList id.
+        let mal_ids: Vec<i32> = anime_ids.values().filter_map(|ids| ids.1).collect();
+        let anilist_media: HashMap<i32, ProviderMedia> =
+            match anilist_query::get_media_by_mal_ids(&mal_ids) {
+                Ok(media) => media
+                    .into_iter()
+                    .filter_map(|media| media.mal_id.map(|mal_id| (mal_id, media)))
+                    .collect(),
+                Err(error) => {
+                    error!(
+                        "error getting AniList media for user_id={}. {}",
+                        user_id, error
+                    );
+                    return None;
+                }
+            };
+
+        let mut entries = Vec::with_capacity(library.len());
+        for entry in library {
+            let kitsu_id = match entry.relationships.anime.data {
+                Some(data) => data.id,
+                None => continue,
+            };
+            let (anilist_id, mal_id) = anime_ids.get(&kitsu_id).cloned().unwrap_or((None, None));
+
+            let media = match (mal_id.and_then(|id| anilist_media.get(&id)), anilist_id) {
+                (Some(media), _) => media.clone(),
+                (None, Some(anilist_id)) => match anime.get(&kitsu_id) {
+                    Some((attributes, _)) => kitsu_media(anilist_id, mal_id, attributes),
+                    None => continue,
+                },
+                (None, None) => {
+                    info!(
+                        "kitsu anime_id={} has no AniList mapping, skipping it",
+                        kitsu_id
+                    );
+                    continue;
+                }
+            };
+
+            let attributes = entry.attributes;
+            entries.push(ProviderEntry {
+                media,
+                // Only current and completed entries are requested, which match AniList's names.
+                status: Some(attributes.status.to_uppercase()),
+                progress: attributes.progress,
+                score: attributes.rating_twenty.map(|rating| rating * 5),
+                start_day: attributes.started_at.as_deref().and_then(parse_date),
+                end_day: attributes.finished_at.as_deref().and_then(parse_date),
+            });
+        }
+
+        Some(entries)
+    }
+
+    // Anime are looked up on AniList by their MyAnimeList ids a page at a time.
+    fn requests_for(&self, entries: usize) -> usize {
+        (entries + anilist_query::MAL_PAGE_SIZE - 1) / anilist_query::MAL_PAGE_SIZE
+    }
+}
+
+fn kitsu_media(
+    anilist_id: i32,
+    mal_id: Option<i32>,
+    attributes: &kitsu_models::AnimeAttributes,
+) -> ProviderMedia {
+    ProviderMedia {
+        id: anilist_id,
+        mal_id,
+        user_title: attributes.canonical_title.clone(),
+        english: attributes.titles.en.clone(),
+        romaji: attributes.titles.en_jp.clone(),
+        native: attributes.titles.ja_jp.clone(),
+        description: attributes.synopsis.clone().unwrap_or_default(),
+        cover: attributes
+            .poster_image
+            .as_ref()
+            .and_then(|image| image.large.clone())
+            .unwrap_or_default(),
+        average: attributes
+            .average_rating
+            .as_ref()
+            .and_then(|rating| rating.parse::<f32>().ok())
+            .map(|rating| rating.round() as i16),
+        // Kitsu's subtypes are AniList's formats in lower case, apart from TV, ONA and OVA.
+        format: attributes
+            .subtype
+            .as_ref()
+            .map(|subtype| subtype.to_uppercase()),
+        episodes: attributes.episode_count,
+        duration: attributes.episode_length,
+    }
+}
+
+// Kitsu gives timestamps such as 2017-01-03T00:00:00.000Z, but only the day is kept.
+fn parse_date(timestamp: &str) -> Option<NaiveDate> {
+    timestamp
+        .get(..10)
+        .and_then(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").ok())
+}
+
+fn send<T: serde::de::DeserializeOwned>(request: RequestBuilder) -> Option<T> {
+    let res_text = request
+        .header(ACCEPT, "application/vnd.api+json")
+        .send()
+        .and_then(|res| res.text());
+
+    match res_text.map(|text| from_str::<T>(text.as_ref())) {
+        Ok(Ok(json)) => Some(json),
+        Ok(Err(error)) => {
+            error!("error parsing kitsu response. Error: {}", error);
+            None
+        }
+        Err(error) => {
+            error!("error fetching from kitsu. Error: {}", error);
+            None
+        }
+    }
+}
+
+static KITSU_URL: &str = "https://kitsu.io/api/edge";
+
+static ANIME_FIELDS: &str = "canonicalTitle,titles,synopsis,posterImage,averageRating,subtype,\
+                             episodeCount,episodeLength,mappings";
@@ -8,48 +8,564 @@
 
 #![feature(proc_macro_hygiene, decl_macro)]
 
+use auth::BearerToken;
+use log::{error, info};
 use rocket::get;
-use rocket::http::Method;
+use rocket::http::uri::{Origin, Uri};
+use rocket::http::{ContentType, Header, Method, Status};
 use rocket::post;
+use rocket::response::content::Content;
 use rocket::response::status::Accepted;
+use rocket::response::status::Custom;
 use rocket::response::status::NotFound;
+use rocket::response::{self, Redirect, Responder, Stream};
 use rocket::routes;
+use rocket::{Data, Request};
 use rocket_contrib::database;
 use rocket_contrib::databases::postgres;
 use rocket_contrib::json::Json;
 use rocket_contrib::serve::StaticFiles;
 use rocket_cors::Error;
 use rocket_cors::{AllowedHeaders, AllowedOrigins};
-use std::thread;
+use serde::Serialize;
+use std::io::Read;
+use std::{env, fs, process};
+use titles::{AcceptLanguage, Localized, TitleLanguage};
 
 mod anilist_models;
 mod anilist_query;
+mod auth;
+mod bench;
+mod card;
+mod changes;
 mod database;
+mod description;
+mod export;
+mod fields;
+mod html;
+mod ics;
+mod kitsu_models;
+mod kitsu_query;
+mod mal;
+mod migrations;
 mod models;
+mod provider;
+mod repository;
+mod scheduler;
+mod sync;
+mod timeline;
+mod titles;
 
 #[database("postgres_connection")]
 pub struct PgDbConn(postgres::Connection);
 
-#[get("/users/<username>")]
+// MyAnimeList exports of very large lists run to a few megabytes.
+static MAX_IMPORT_SIZE: u64 = 16 * 1024 * 1024;
+
+// Errors for routes under a user. Old names and names in a different case are redirected to the
+// user's current name rather than treated as missing.
+#[derive(Debug)]
+enum UserError {
+    Moved(String),
+    Failed(Custom<String>),
+}
+
+impl<'r> Responder<'r> for UserError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            // A 301 may be followed with a GET, so anything else keeps its method with a 308.
+            UserError::Moved(location) if request.method() == Method::Get => {
+                Redirect::moved(location).respond_to(request)
+            }
+            UserError::Moved(location) => Redirect::permanent(location).respond_to(request),
+            UserError::Failed(error) => error.respond_to(request),
+        }
+    }
+}
+
+impl UserError {
+    fn not_found() -> UserError {
+        UserError::Failed(Custom(
+            Status::NotFound,
+            "User or list not found".to_owned(),
+        ))
+    }
+}
+
+// Finds the stored username for the one in a request's path. When they differ the request is
+// redirected to the same path and query under the stored name.
+fn resolve_user(
+    username: &str,
+    uri: &Origin,
+    database_conn: &PgDbConn,
+) -> Result<String, UserError> {
+    match database::canonical_username(username, database_conn) {
+        Some(canonical) if canonical == username => Ok(canonical),
+        Some(canonical) => {
+            // Paths look like /users/<username>/..., so only the second segment changes.
+            let rest = uri
+                .path()
+                .splitn(4, '/')
+                .nth(3)
+                .map(|rest| format!("/{}", rest))
+                .unwrap_or_default();
+            let mut location = format!("/users/{}{}", Uri::percent_encode(&canonical), rest);
+            if let Some(query) = uri.query() {
+                location.push('?');
+                location.push_str(query);
+            }
+            Err(UserError::Moved(location))
+        }
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>?<as_of>&<include_removed>&<title>&<description>&<fields>")]
+#[allow(clippy::too_many_arguments)]
 fn user(
     username: String,
+    as_of: Option<models::QueryDate>,
+    include_removed: Option<bool>,
+    title: Option<String>,
+    description: Option<String>,
+    fields: Option<String>,
+    language: AcceptLanguage,
+    uri: &Origin,
     database_conn: PgDbConn,
-) -> Result<Json<models::RestResponse>, NotFound<String>> {
-    match database::get_list(username.as_ref(), &database_conn) {
-        Some(list) => Ok(Json(list)),
-        None => Err(NotFound("User or list not found".to_owned())),
+) -> Result<Localized<Json<fields::Selected<models::RestResponse>>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    let options = models::ListOptions {
+        as_of: as_of.map(|date| date.0),
+        include_removed: include_removed.unwrap_or(false),
+        title: title_language(language, title)?,
+        description: description::DescriptionFormat::from_param(description)
+            .map_err(|e| UserError::Failed(Custom(Status::BadRequest, e)))?,
+        fields: parse_fields(fields, &fields::LIST_FIELDS)?,
+    };
+    match database::get_list(username.as_ref(), &options, &database_conn) {
+        Some(list) => {
+            select_fields(list, &["users", "list"], options.fields.as_deref()).map(Localized)
+        }
+        None => Err(UserError::not_found()),
     }
 }
 
+fn title_language(
+    language: AcceptLanguage,
+    title: Option<String>,
+) -> Result<TitleLanguage, UserError> {
+    language
+        .with_param(title)
+        .map_err(|e| UserError::Failed(Custom(Status::BadRequest, e)))
+}
+
+fn parse_fields(
+    param: Option<String>,
+    available: &[&'static str],
+) -> Result<Option<Vec<&'static str>>, UserError> {
+    fields::parse(param, available).map_err(|e| UserError::Failed(Custom(Status::BadRequest, e)))
+}
+
+fn select_fields<T: Serialize>(
+    response: T,
+    path: &[&str],
+    fields: Option<&[&str]>,
+) -> Result<Json<fields::Selected<T>>, UserError> {
+    fields::select(response, path, fields)
+        .map(Json)
+        .map_err(|e| UserError::Failed(Custom(Status::InternalServerError, e.to_string())))
+}
+
+#[get("/users/<username>/timeline.svg?<width>&<from>&<to>&<color>&<title>")]
+#[allow(clippy::too_many_arguments)]
+fn user_timeline(
+    username: String,
+    width: Option<u32>,
+    from: Option<models::QueryDate>,
+    to: Option<models::QueryDate>,
+    color: Option<String>,
+    title: Option<String>,
+    language: AcceptLanguage,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Localized<Content<String>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    let list_options = models::ListOptions {
+        title: title_language(language, title)?,
+        ..Default::default()
+    };
+    match database::get_list(username.as_ref(), &list_options, &database_conn) {
+        Some(list) => {
+            let options = timeline::TimelineOptions {
+                width,
+                from: from.map(|d| d.0),
+                to: to.map(|d| d.0),
+                color: timeline::ColorBy::from_param(color),
+            };
+            let svg = timeline::render(&list.users, &options);
+            Ok(Localized(Content(ContentType::SVG, svg)))
+        }
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/card.png")]
+fn user_card(
+    username: String,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Content<Vec<u8>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    match database::get_list(
+        username.as_ref(),
+        &models::ListOptions::default(),
+        &database_conn,
+    ) {
+        Some(list) => match card::get_card(&list.users) {
+            Some(png) => Ok(Content(ContentType::PNG, png)),
+            None => Err(UserError::Failed(Custom(
+                Status::InternalServerError,
+                "Card could not be rendered".to_owned(),
+            ))),
+        },
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/history.ics?<missing>&<title>")]
+fn user_calendar(
+    username: String,
+    missing: Option<String>,
+    title: Option<String>,
+    language: AcceptLanguage,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Localized<Content<String>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    let list_options = models::ListOptions {
+        title: title_language(language, title)?,
+        ..Default::default()
+    };
+    match database::get_list(username.as_ref(), &list_options, &database_conn) {
+        Some(list) => {
+            let missing = ics::MissingDates::from_param(missing);
+            Ok(Localized(Content(
+                ContentType::Calendar,
+                ics::render(&list.users, &missing),
+            )))
+        }
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/export.csv?<columns>&<title>")]
+fn user_export_csv(
+    username: String,
+    columns: Option<String>,
+    title: Option<String>,
+    language: AcceptLanguage,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Localized<Content<Stream<export::Export>>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    export_list(
+        username.as_ref(),
+        columns,
+        export::Delimiter::Comma,
+        title_language(language, title)?,
+        &database_conn,
+    )
+    .map(|csv| Localized(Content(ContentType::CSV, csv)))
+}
+
+#[get("/users/<username>/export.tsv?<columns>&<title>")]
+fn user_export_tsv(
+    username: String,
+    columns: Option<String>,
+    title: Option<String>,
+    language: AcceptLanguage,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Localized<Content<Stream<export::Export>>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    export_list(
+        username.as_ref(),
+        columns,
+        export::Delimiter::Tab,
+        title_language(language, title)?,
+        &database_conn,
+    )
+    .map(|tsv| {
+        Localized(Content(
+            ContentType::new("text", "tab-separated-values"),
+            tsv,
+        ))
+    })
+}
+
+fn export_list(
+    username: &str,
+    columns: Option<String>,
+    delimiter: export::Delimiter,
+    title: TitleLanguage,
+    database_conn: &PgDbConn,
+) -> Result<Stream<export::Export>, UserError> {
+    let columns = export::parse_columns(columns)
+        .map_err(|e| UserError::Failed(Custom(Status::BadRequest, e)))?;
+    match database::get_list_items(username, database_conn) {
+        Some(items) if !items.is_empty() => {
+            match export::Export::new(items, columns, delimiter, title) {
+                Ok(export) => Ok(Stream::from(export)),
+                Err(error) => {
+                    error!("error writing export. Error: {}", error);
+                    Err(UserError::Failed(Custom(
+                        Status::InternalServerError,
+                        "List could not be exported".to_owned(),
+                    )))
+                }
+            }
+        }
+        _ => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/export/mal.xml")]
+fn user_export_mal(
+    username: String,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Content<String>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    match database::get_list_items(username.as_ref(), &database_conn) {
+        Some(items) if !items.is_empty() => {
+            Ok(Content(ContentType::XML, mal::render_export(&items)))
+        }
+        _ => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/changes?<limit>&<fields>")]
+fn user_changes(
+    username: String,
+    limit: Option<i64>,
+    fields: Option<String>,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Json<fields::Selected<models::ChangesResponse>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    let limit = limit.unwrap_or(50).clamp(1, 500);
+    let fields = parse_fields(fields, &fields::CHANGE_FIELDS)?;
+    match database::get_changes(username.as_ref(), limit, fields.as_deref(), &database_conn) {
+        Some(changes) => select_fields(changes, &["changes"], fields.as_deref()),
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/users/<username>/anime/<anime_id>/history")]
+fn user_anime_history(
+    username: String,
+    anime_id: i32,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Json<models::HistoryResponse>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    match database::get_anime_history(username.as_ref(), anime_id, &database_conn) {
+        Some(history) => Ok(Json(history)),
+        None => Err(UserError::not_found()),
+    }
+}
+
+// Only local users' entries, removed by a later import, can be restored. A provider user's next sync
+// would remove the entry again, so theirs come back by being added on the provider, which keeps the
+// dates and score stored here when the provider has none.
+#[post("/users/<username>/anime/<anime_id>/restore")]
+fn restore(
+    username: String,
+    anime_id: i32,
+    token: BearerToken,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<String, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    let (provider_name, name) = provider::split_username(username.as_ref());
+    if provider_name != "local" {
+        return Err(UserError::Failed(Custom(
+            Status::Forbidden,
+            format!(
+                "{} syncs from {}, so entries come back by adding them there",
+                username, provider_name
+            ),
+        )));
+    }
+    let user_id =
+        database::find_local_user_id(name, &database_conn).ok_or_else(UserError::not_found)?;
+    match token.0 {
+        None => {
+            return Err(UserError::Failed(Custom(
+                Status::Unauthorized,
+                format!("{}'s token is needed to restore their entries", username),
+            )))
+        }
+        Some(token) if !database::owns_user(user_id, token.as_ref(), &database_conn) => {
+            return Err(UserError::Failed(Custom(
+                Status::Forbidden,
+                format!("The token is not the one for {}", username),
+            )))
+        }
+        Some(_) => (),
+    }
+
+    match database::restore_entry(user_id, anime_id, &database_conn) {
+        Some(true) => Ok("Restored".to_owned()),
+        Some(false) => Err(UserError::Failed(Custom(
+            Status::NotFound,
+            "No removed entry for that anime".to_owned(),
+        ))),
+        None => Err(UserError::Failed(Custom(
+            Status::InternalServerError,
+            "Could not restore the entry".to_owned(),
+        ))),
+    }
+}
+
+#[get("/users/<username>/search?<q>&<limit>&<fields>")]
+fn user_search(
+    username: String,
+    q: String,
+    limit: Option<i64>,
+    fields: Option<String>,
+    uri: &Origin,
+    database_conn: PgDbConn,
+) -> Result<Json<fields::Selected<models::SearchResponse>>, UserError> {
+    let username = resolve_user(username.as_ref(), uri, &database_conn)?;
+    // Every title contains the empty string, so a blank search would match the whole list.
+    if q.trim().is_empty() {
+        return Err(UserError::Failed(Custom(
+            Status::BadRequest,
+            "A search is required".to_owned(),
+        )));
+    }
+    let limit = limit.unwrap_or(20).clamp(1, 100);
+    let fields = parse_fields(fields, &fields::SEARCH_FIELDS)?;
+    match database::search_list(
+        username.as_ref(),
+        q.trim(),
+        limit,
+        fields.as_deref(),
+        &database_conn,
+    ) {
+        Some(results) => select_fields(results, &["results"], fields.as_deref()),
+        None => Err(UserError::not_found()),
+    }
+}
+
+#[get("/anime/<anime_id>")]
+fn anime(
+    anime_id: i32,
+    database_conn: PgDbConn,
+) -> Result<Json<models::AnimeResponse>, Custom<String>> {
+    match database::get_anime(anime_id, &database_conn) {
+        Ok(Some(anime)) => Ok(Json(anime)),
+        Ok(None) => Err(Custom(Status::NotFound, "Anime not found".to_owned())),
+        Err(_) => Err(Custom(
+            Status::InternalServerError,
+            "Anime could not be loaded".to_owned(),
+        )),
+    }
+}
+
+#[get("/anime?<search>&<limit>")]
+fn anime_search(
+    search: String,
+    limit: Option<i64>,
+    database_conn: PgDbConn,
+) -> Result<Json<models::AnimeSearchResponse>, Custom<String>> {
+    let limit = limit.unwrap_or(20).clamp(1, 100);
+    match database::search_anime(search.trim(), limit, &database_conn) {
+        Some(results) => Ok(Json(results)),
+        None => Err(Custom(
+            Status::InternalServerError,
+            "Anime could not be searched".to_owned(),
+        )),
+    }
+}
+
+#[derive(Debug, Responder)]
+#[response(status = 429)]
+struct TooManyRequests(String, Header<'static>);
+
+#[derive(Debug, Responder)]
+enum UpdateError {
+    NotFound(NotFound<String>),
+    TooSoon(TooManyRequests),
+}
+
 #[post("/users/<username>")]
-fn update(username: String, database_conn: PgDbConn) -> Result<Accepted<String>, NotFound<String>> {
-    match anilist_query::get_id(username.as_ref()) {
+fn update(username: String, database_conn: PgDbConn) -> Result<Accepted<String>, UpdateError> {
+    let (provider, name) = match provider::for_username(username.as_ref()) {
+        Some(provider) => provider,
+        None => {
+            return Err(UpdateError::NotFound(NotFound(
+                "User has no provider to update from".to_owned(),
+            )))
+        }
+    };
+    if let Err(seconds) = sync::claim_update(username.as_ref(), &database_conn) {
+        return Err(UpdateError::TooSoon(TooManyRequests(
+            format!("Updated too recently, try again in {} seconds", seconds),
+            Header::new("Retry-After", seconds.to_string()),
+        )));
+    }
+
+    match provider.get_user(name) {
         Some(user) => {
-            database::update_user_profile(user.clone(), &database_conn);
-            thread::spawn(move || database::update_entries(user.id));
-            Ok(Accepted(Some("Added to the queue".to_owned())))
+            let external_id = user.id;
+            match database::update_user_profile(provider.as_ref(), user, &database_conn) {
+                Some(id) => {
+                    let message = match sync::request(provider, id, external_id) {
+                        sync::SyncRequest::Started => "Added to the queue",
+                        sync::SyncRequest::Queued => "Queued after the update in progress",
+                        sync::SyncRequest::AlreadyQueued => "Already queued",
+                    };
+                    Ok(Accepted(Some(message.to_owned())))
+                }
+                None => Err(UpdateError::NotFound(NotFound(
+                    "User could not be saved".to_owned(),
+                ))),
+            }
+        }
+        None => Err(UpdateError::NotFound(NotFound("User not found".to_owned()))),
+    }
+}
+
+#[post("/import/mal?<username>", data = "<export>")]
+fn import_mal(
+    username: Option<String>,
+    token: BearerToken,
+    export: Data,
+    database_conn: PgDbConn,
+) -> Result<Json<models::ImportReport>, Custom<String>> {
+    let mut xml = String::new();
+    if let Err(error) = export.open().take(MAX_IMPORT_SIZE).read_to_string(&mut xml) {
+        return Err(Custom(
+            Status::BadRequest,
+            format!("Could not read export: {}", error),
+        ));
+    }
+
+    let access = mal::Access::Token(token.0.as_deref());
+    match mal::import(&xml, username, access, &database_conn) {
+        Ok(report) => Ok(Json(report)),
+        Err(error) => {
+            let status = match error {
+                mal::ImportError::Invalid(_) => Status::BadRequest,
+                mal::ImportError::Unauthorized(_) => Status::Unauthorized,
+                mal::ImportError::Forbidden(_) => Status::Forbidden,
+                mal::ImportError::Upstream(_) => Status::BadGateway,
+                mal::ImportError::Failed(_) => Status::InternalServerError,
+            };
+            Err(Custom(status, error.to_string()))
         }
-        None => Err(NotFound("User not found".to_owned())),
     }
 }
 
@@ -58,6 +574,15 @@ fn main() -> Result<(), Error> {
         std::process::abort()
     }
 
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        run_command(&args);
+        return Ok(());
+    }
+
+    migrate();
+    scheduler::start(scheduler::SchedulerOptions::from_env());
+
     let allowed_origins = AllowedOrigins::some_exact(&[
         "http://localhost:4200",
         "https://anihistory.moe",
@@ -79,7 +604,26 @@ fn main() -> Result<(), Error> {
 
     rocket::ignite()
         .mount("/", StaticFiles::from("static"))
-        .mount("/", routes![update, user])
+        .mount(
+            "/",
+            routes![
+                update,
+                restore,
+                user,
+                user_timeline,
+                user_card,
+                user_calendar,
+                user_export_csv,
+                user_export_tsv,
+                user_export_mal,
+                user_changes,
+                user_anime_history,
+                user_search,
+                anime,
+                anime_search,
+                import_mal
+            ],
+        )
         .attach(cors)
         .attach(PgDbConn::fairing())
         .launch();
@@ -87,6 +631,70 @@ fn main() -> Result<(), Error> {
     Ok(())
 }
 
+fn run_command(args: &[String]) {
+    match args[0].as_ref() {
+        "migrate" => migrate(),
+        "bench-sync" => {
+            let size = match args.get(1).map(|size| size.parse::<usize>()) {
+                Some(Ok(size)) => size,
+                Some(Err(_)) => {
+                    eprintln!("The number of entries must be a positive number");
+                    process::exit(1);
+                }
+                None => bench::DEFAULT_SIZE,
+            };
+
+            let connection = database::establish_connection();
+            if let Err(error) = bench::bench_sync(size, &connection) {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        }
+        "import-mal" if args.len() >= 2 => {
+            let xml = match fs::read_to_string(&args[1]) {
+                Ok(xml) => xml,
+                Err(error) => {
+                    eprintln!("Could not read {}: {}", args[1], error);
+                    process::exit(1);
+                }
+            };
+            let username = args.get(2).cloned();
+
+            let connection = database::establish_connection();
+            match mal::import(&xml, username, mal::Access::CommandLine, &connection) {
+                Ok(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => {
+            eprintln!("Usage: anihistory_server [migrate | bench-sync [entries] | import-mal <export.xml> [username]]");
+            process::exit(1);
+        }
+    }
+}
+
+// Applies any migrations the database is missing. Nothing can be served from an outdated schema, so
+// failing here stops the server.
+fn migrate() {
+    let connection = database::establish_connection();
+    match migrations::run(&connection) {
+        Ok(0) => info!("Database schema is up to date"),
+        Ok(count) => info!("Applied {} migrations", count),
+        Err(error) => {
+            error!("{}", error);
+            process::exit(1);
+        }
+    }
+    match database::fill_descriptions(&connection) {
+        Ok(0) => {}
+        Ok(count) => info!("Sanitized the descriptions of {} anime", count),
+        Err(error) => error!("error sanitizing descriptions. Error: {}", error),
+    }
+}
+
 fn setup_logger() -> Result<(), fern::InitError> {
     fern::Dispatch::new()
         .format(move |out, message, record| {
@@ -0,0 +1,486 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::anilist_query;
+use crate::database::LocalUser;
+use crate::html::escape;
+use crate::provider::{self, ProviderEntry, ProviderMedia};
+use crate::{database, models};
+use chrono::NaiveDate;
+use log::error;
+use postgres::Connection;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Write};
+
+pub struct MalExport {
+    pub user_name: Option<String>,
+    pub entries: Vec<MalEntry>,
+}
+
+pub struct MalEntry {
+    pub mal_id: i32,
+    pub title: String,
+    pub watched_episodes: Option<i32>,
+    pub start_day: Option<NaiveDate>,
+    pub end_day: Option<NaiveDate>,
+    pub score: Option<i16>,
+    pub status: Option<&'static str>,
+}
+
+/// Who an import comes from. The command line can write to any local user, while a request has to
+/// send the token of the user it writes to unless it makes a new one.
+pub enum Access<'a> {
+    CommandLine,
+    Token(Option<&'a str>),
+}
+
+pub enum ImportError {
+    Invalid(String),
+    /// No token was sent for an existing user.
+    Unauthorized(String),
+    /// The token sent isn't the user's.
+    Forbidden(String),
+    /// AniList couldn't be reached to look the anime up.
+    Upstream(String),
+    Failed(String),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Invalid(message)
+            | ImportError::Unauthorized(message)
+            | ImportError::Forbidden(message)
+            | ImportError::Upstream(message)
+            | ImportError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Imports a MyAnimeList export into the list of a local user, who is made if they don't exist yet.
+/// Like a sync, only watching and completed entries are kept, and entries missing from the export
+/// are marked removed.
+pub fn import(
+    xml: &str,
+    username: Option<String>,
+    access: Access,
+    connection: &Connection,
+) -> Result<models::ImportReport, ImportError> {
+    let export = parse_export(xml).map_err(ImportError::Invalid)?;
+    let name = match username.or(export.user_name) {
+        Some(name) if !name.trim().is_empty() => name.trim().to_owned(),
+        _ => {
+            return Err(ImportError::Invalid(
+                "A username is required to import a list".to_owned(),
+            ))
+        }
+    };
+    if !valid_local_name(&name) {
+        return Err(ImportError::Invalid(format!(
+            "Usernames must be {} to {} letters, numbers, underscores or hyphens",
+            MIN_NAME_LENGTH, MAX_NAME_LENGTH
+        )));
+    }
+
+    // Checked before anything is fetched, and again below in case the user was made meanwhile.
+    if let Some(user_id) = database::find_local_user_id(name.as_ref(), connection) {
+        check_access(user_id, &access, &name, connection)?;
+    }
+    let user = provider::qualified_username("local", &name);
+
+    let (wanted, skipped): (Vec<MalEntry>, Vec<MalEntry>) = export
+        .entries
+        .into_iter()
+        .partition(|entry| entry.status == Some("CURRENT") || entry.status == Some("COMPLETED"));
+
+    let mal_ids: Vec<i32> = wanted.iter().map(|entry| entry.mal_id).collect();
+    let media: HashMap<i32, ProviderMedia> = anilist_query::get_media_by_mal_ids(&mal_ids)
+        .map_err(|error| {
+            error!("error mapping import for user_name={}. {}", name, error);
+            ImportError::Upstream("Could not look up the anime on AniList".to_owned())
+        })?
+        .into_iter()
+        .filter_map(|media| media.mal_id.map(|mal_id| (mal_id, media)))
+        .collect();
+
+    let mut entries = Vec::with_capacity(wanted.len());
+    let mut unmapped = Vec::new();
+    for entry in wanted {
+        match media.get(&entry.mal_id) {
+            Some(media) => entries.push(ProviderEntry {
+                media: media.clone(),
+                status: entry.status.map(|status| status.to_owned()),
+                progress: entry.watched_episodes,
+                score: entry.score,
+                start_day: entry.start_day,
+                end_day: entry.end_day,
+            }),
+            None => unmapped.push(models::UnmappedEntry {
+                mal_id: entry.mal_id,
+                title: entry.title,
+            }),
+        }
+    }
+
+    // Made only now, so an import that fails before this doesn't leave a user behind whose token
+    // nobody has.
+    let (user_id, token) = match database::get_or_create_local_user(name.as_ref(), connection) {
+        Some(LocalUser::Created(user_id, token)) => (user_id, Some(token)),
+        Some(LocalUser::Existing(user_id)) => {
+            check_access(user_id, &access, &name, connection)?;
+            (user_id, None)
+        }
+        None => {
+            return Err(ImportError::Failed(format!(
+                "Could not create a local user named {}",
+                name
+            )))
+        }
+    };
+
+    let imported = entries.len();
+    database::import_entries(user_id, entries, connection)
+        .ok_or_else(|| ImportError::Failed("Could not save the imported entries".to_owned()))?;
+
+    Ok(models::ImportReport {
+        user,
+        token,
+        imported,
+        skipped: skipped.len(),
+        unmapped,
+    })
+}
+
+static MIN_NAME_LENGTH: usize = 2;
+static MAX_NAME_LENGTH: usize = 32;
+
+// Local names end up in URLs, redirects and the card cache's file names, so they are kept to the
+// characters MyAnimeList allows.
+fn valid_local_name(name: &str) -> bool {
+    (MIN_NAME_LENGTH..=MAX_NAME_LENGTH).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn check_access(
+    user_id: i32,
+    access: &Access,
+    name: &str,
+    connection: &Connection,
+) -> Result<(), ImportError> {
+    match access {
+        Access::CommandLine => Ok(()),
+        Access::Token(Some(token)) if database::owns_user(user_id, token, connection) => Ok(()),
+        Access::Token(Some(_)) => Err(ImportError::Forbidden(format!(
+            "The token is not the one for {}",
+            name
+        ))),
+        Access::Token(None) => Err(ImportError::Unauthorized(format!(
+            "{} already exists, so their token is needed to import into their list",
+            name
+        ))),
+    }
+}
+
+pub fn parse_export(xml: &str) -> Result<MalExport, String> {
+    let document =
+        Document::parse(xml).map_err(|error| format!("Invalid MyAnimeList export: {}", error))?;
+    let root = document.root_element();
+    if !root.has_tag_name("myanimelist") {
+        return Err("Invalid MyAnimeList export: missing <myanimelist>".to_owned());
+    }
+
+    let user_name = root
+        .children()
+        .find(|node| node.has_tag_name("myinfo"))
+        .and_then(|info| child_text(info, "user_name"))
+        .map(|name| name.to_owned());
+
+    let mut entries = Vec::new();
+    for anime in root.children().filter(|node| node.has_tag_name("anime")) {
+        let mal_id = match child_text(anime, "series_animedb_id").and_then(|id| id.parse().ok()) {
+            Some(mal_id) => mal_id,
+            None => continue,
+        };
+
+        entries.push(MalEntry {
+            mal_id,
+            title: child_text(anime, "series_title")
+                .unwrap_or_default()
+                .to_owned(),
+            watched_episodes: child_text(anime, "my_watched_episodes").and_then(|e| e.parse().ok()),
+            start_day: child_text(anime, "my_start_date").and_then(parse_mal_date),
+            end_day: child_text(anime, "my_finish_date").and_then(parse_mal_date),
+            // Scores are whole numbers out of 10, and anything else in an upload is ignored.
+            score: child_text(anime, "my_score")
+                .and_then(|score| score.parse::<i16>().ok())
+                .filter(|score| (0..=10).contains(score))
+                .map(|score| score * 10),
+            status: child_text(anime, "my_status").and_then(anilist_status),
+        });
+    }
+
+    Ok(MalExport { user_name, entries })
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+        .map(|text| text.trim())
+}
+
+// Unknown parts of a date are exported as zeros, and a date missing its day or month can't be
+// stored.
+fn parse_mal_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+// Exports have used both the status names and MyAnimeList's numeric status codes.
+fn anilist_status(status: &str) -> Option<&'static str> {
+    match status {
+        "Watching" | "1" => Some("CURRENT"),
+        "Completed" | "2" => Some("COMPLETED"),
+        "On-Hold" | "3" => Some("PAUSED"),
+        "Dropped" | "4" => Some("DROPPED"),
+        "Plan to Watch" | "6" => Some("PLANNING"),
+        _ => None,
+    }
+}
+
+/// Renders a user's list as a MyAnimeList import file. Entries whose anime has no MyAnimeList id
+/// cannot be imported there, so they are listed in a comment instead.
+pub fn render_export(items: &[models::ListItemMap]) -> String {
+    let (mapped, unmapped): (Vec<&models::ListItemMap>, Vec<&models::ListItemMap>) =
+        items.iter().partition(|item| item.anime.mal_id.is_some());
+
+    let count = |status: &str| {
+        mapped
+            .iter()
+            .filter(|item| mal_status(&item.list_item) == status)
+            .count()
+    };
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\" ?>\n<myanimelist>\n");
+
+    xml.push_str("  <myinfo>\n");
+    if let Some(item) = items.first() {
+        push_tag(&mut xml, "user_name", escape(&item.user.name));
+    }
+    push_tag(&mut xml, "user_export_type", 1);
+    push_tag(&mut xml, "user_total_anime", mapped.len());
+    push_tag(&mut xml, "user_total_watching", count("Watching"));
+    push_tag(&mut xml, "user_total_completed", count("Completed"));
+    push_tag(&mut xml, "user_total_onhold", count("On-Hold"));
+    push_tag(&mut xml, "user_total_dropped", count("Dropped"));
+    push_tag(&mut xml, "user_total_plantowatch", count("Plan to Watch"));
+    xml.push_str("  </myinfo>\n");
+
+    for item in mapped.iter() {
+        let list_item = &item.list_item;
+        let anime = &item.anime;
+
+        xml.push_str("  <anime>\n");
+        push_tag(&mut xml, "series_animedb_id", anime.mal_id.unwrap_or(0));
+        push_tag(&mut xml, "series_title", escape(title(item)));
+        push_tag(
+            &mut xml,
+            "series_type",
+            series_type(anime.format.as_deref()),
+        );
+        push_tag(&mut xml, "series_episodes", anime.episodes.unwrap_or(0));
+        push_tag(&mut xml, "my_id", 0);
+        push_tag(&mut xml, "my_watched_episodes", watched_episodes(item));
+        push_tag(&mut xml, "my_start_date", mal_date(list_item.start_day));
+        push_tag(&mut xml, "my_finish_date", mal_date(list_item.end_day));
+        push_tag(&mut xml, "my_score", mal_score(list_item.score));
+        push_tag(&mut xml, "my_status", mal_status(list_item));
+        push_tag(&mut xml, "my_times_watched", 0);
+        push_tag(&mut xml, "update_on_import", 1);
+        xml.push_str("  </anime>\n");
+    }
+
+    if !unmapped.is_empty() {
+        xml.push_str("  <!-- No MyAnimeList id for AniList ids:");
+        for item in unmapped {
+            let _ = write!(xml, " {}", item.anime.anime_id);
+        }
+        xml.push_str(" -->\n");
+    }
+
+    xml.push_str("</myanimelist>\n");
+    xml
+}
+
+fn push_tag<T: Display>(xml: &mut String, name: &str, value: T) {
+    let _ = writeln!(xml, "    <{0}>{1}</{0}>", name, value);
+}
+
+fn title(item: &models::ListItemMap) -> &str {
+    item.anime
+        .romaji
+        .as_ref()
+        .or(item.list_item.user_title.as_ref())
+        .or(item.anime.english.as_ref())
+        .or(item.anime.native.as_ref())
+        .map(|t| t.as_str())
+        .unwrap_or("")
+}
+
+fn series_type(format: Option<&str>) -> &'static str {
+    match format {
+        Some("TV") | Some("TV_SHORT") => "TV",
+        Some("MOVIE") => "Movie",
+        Some("SPECIAL") => "Special",
+        Some("OVA") => "OVA",
+        Some("ONA") => "ONA",
+        Some("MUSIC") => "Music",
+        _ => "Unknown",
+    }
+}
+
+fn mal_status(list_item: &models::ListItem) -> &'static str {
+    match list_item.status.as_deref() {
+        Some("CURRENT") | Some("REPEATING") => "Watching",
+        Some("COMPLETED") => "Completed",
+        Some("PAUSED") => "On-Hold",
+        Some("DROPPED") => "Dropped",
+        Some("PLANNING") => "Plan to Watch",
+        // Rows synced before statuses were stored only came from the completed and watching lists.
+        _ if list_item.end_day.is_some() => "Completed",
+        _ => "Watching",
+    }
+}
+
+fn watched_episodes(item: &models::ListItemMap) -> i32 {
+    match item.list_item.progress {
+        Some(progress) => progress,
+        None if mal_status(&item.list_item) == "Completed" => item.anime.episodes.unwrap_or(0),
+        None => 0,
+    }
+}
+
+// Scores are stored as POINT_100 and MyAnimeList uses whole numbers out of 10.
+fn mal_score(score: Option<i16>) -> i16 {
+    match score {
+        Some(score) if score > 0 => ((score + 5) / 10).clamp(1, 10),
+        _ => 0,
+    }
+}
+
+fn mal_date(date: Option<NaiveDate>) -> String {
+    match date {
+        Some(date) => date.format("%Y-%m-%d").to_string(),
+        None => "0000-00-00".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list_item(status: Option<&str>, end_day: Option<NaiveDate>) -> models::ListItem {
+        models::ListItem {
+            user_id: 1,
+            anime_id: 1,
+            user_title: None,
+            start_day: None,
+            end_day,
+            score: None,
+            status: status.map(|status| status.to_owned()),
+            progress: None,
+            removed_at: None,
+        }
+    }
+
+    #[test]
+    fn rounds_scores_to_tenths() {
+        assert_eq!(mal_score(None), 0);
+        assert_eq!(mal_score(Some(0)), 0);
+        assert_eq!(mal_score(Some(1)), 1);
+        assert_eq!(mal_score(Some(74)), 7);
+        assert_eq!(mal_score(Some(75)), 8);
+        assert_eq!(mal_score(Some(100)), 10);
+        assert_eq!(mal_score(Some(120)), 10);
+    }
+
+    #[test]
+    fn writes_missing_dates_as_zeros() {
+        assert_eq!(mal_date(None), "0000-00-00");
+        assert_eq!(mal_date(NaiveDate::from_ymd_opt(2020, 3, 4)), "2020-03-04");
+    }
+
+    #[test]
+    fn maps_statuses() {
+        assert_eq!(mal_status(&list_item(Some("REPEATING"), None)), "Watching");
+        assert_eq!(mal_status(&list_item(Some("PAUSED"), None)), "On-Hold");
+        assert_eq!(mal_status(&list_item(None, None)), "Watching");
+        let ended = NaiveDate::from_ymd_opt(2020, 3, 4);
+        assert_eq!(mal_status(&list_item(None, ended)), "Completed");
+    }
+
+    static EXPORT: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+        <myanimelist>
+            <myinfo><user_name> Foo </user_name></myinfo>
+            <anime>
+                <series_animedb_id>16498</series_animedb_id>
+                <series_title><![CDATA[Shingeki no Kyojin]]></series_title>
+                <my_watched_episodes>25</my_watched_episodes>
+                <my_start_date>2013-04-07</my_start_date>
+                <my_finish_date>0000-00-00</my_finish_date>
+                <my_score>9</my_score>
+                <my_status>Completed</my_status>
+            </anime>
+            <anime>
+                <series_animedb_id>1</series_animedb_id>
+                <my_score>3277</my_score>
+                <my_status>6</my_status>
+            </anime>
+            <anime>
+                <series_title>No id</series_title>
+            </anime>
+        </myanimelist>"#;
+
+    #[test]
+    fn parses_entries() {
+        let export = parse_export(EXPORT).ok().unwrap();
+
+        assert_eq!(export.user_name.as_deref(), Some("Foo"));
+        assert_eq!(export.entries.len(), 2);
+        let entry = &export.entries[0];
+        assert_eq!(entry.mal_id, 16498);
+        assert_eq!(entry.title, "Shingeki no Kyojin");
+        assert_eq!(entry.watched_episodes, Some(25));
+        assert_eq!(entry.start_day, NaiveDate::from_ymd_opt(2013, 4, 7));
+        assert_eq!(entry.end_day, None);
+        assert_eq!(entry.score, Some(90));
+        assert_eq!(entry.status, Some("COMPLETED"));
+        assert_eq!(export.entries[1].status, Some("PLANNING"));
+        assert_eq!(export.entries[1].score, None);
+        assert_eq!(export.entries[1].title, "");
+    }
+
+    #[test]
+    fn accepts_only_plain_local_names() {
+        assert!(valid_local_name("Foo_bar-2"));
+        assert!(!valid_local_name("F"));
+        assert!(!valid_local_name(&"a".repeat(MAX_NAME_LENGTH + 1)));
+        for name in &["../cards", "a/b", "a b", "a\nb", "ä1", "a:b", "a%2F"] {
+            assert!(!valid_local_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(parse_export("<anime></anime>").is_err());
+        assert!(parse_export("not xml").is_err());
+    }
+}
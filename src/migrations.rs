@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use log::{error, info};
+use postgres::Connection;
+
+// Migrations run in order and are recorded by version, so never edit or reorder one that has been
+// released. Add a new one instead.
+static MIGRATIONS: [(i32, &str, &str); 15] = [
+    (
+        1,
+        "create_tables",
+        include_str!("../migrations/0001_create_tables.sql"),
+    ),
+    (
+        2,
+        "list_details",
+        include_str!("../migrations/0002_list_details.sql"),
+    ),
+    (
+        3,
+        "providers",
+        include_str!("../migrations/0003_providers.sql"),
+    ),
+    (
+        4,
+        "sync_tracking",
+        include_str!("../migrations/0004_sync_tracking.sql"),
+    ),
+    (
+        5,
+        "last_viewed",
+        include_str!("../migrations/0005_last_viewed.sql"),
+    ),
+    (
+        6,
+        "sync_runs",
+        include_str!("../migrations/0006_sync_runs.sql"),
+    ),
+    (
+        7,
+        "list_history",
+        include_str!("../migrations/0007_list_history.sql"),
+    ),
+    (
+        8,
+        "removed_entries",
+        include_str!("../migrations/0008_removed_entries.sql"),
+    ),
+    (
+        9,
+        "anime_search",
+        include_str!("../migrations/0009_anime_search.sql"),
+    ),
+    (
+        10,
+        "title_search",
+        include_str!("../migrations/0010_title_search.sql"),
+    ),
+    (
+        11,
+        "description_variants",
+        include_str!("../migrations/0011_description_variants.sql"),
+    ),
+    (
+        12,
+        "user_tokens",
+        include_str!("../migrations/0012_user_tokens.sql"),
+    ),
+    (
+        13,
+        "local_user_ids",
+        include_str!("../migrations/0013_local_user_ids.sql"),
+    ),
+    (
+        14,
+        "update_attempts",
+        include_str!("../migrations/0014_update_attempts.sql"),
+    ),
+    (
+        15,
+        "description_links",
+        include_str!("../migrations/0015_description_links.sql"),
+    ),
+];
+
+// Any number works as long as nothing else takes the same advisory lock.
+static MIGRATION_LOCK: i64 = 0x616e_6968_6973;
+
+/// Brings the database up to date, returning how many migrations were applied. Each migration runs
+/// in its own transaction, and concurrent servers wait for each other rather than racing.
+pub fn run(connection: &Connection) -> Result<usize, String> {
+    connection
+        .batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version integer PRIMARY KEY,
+                name text NOT NULL,
+                applied_at timestamp NOT NULL DEFAULT now()
+            )",
+        )
+        .map_err(|error| format!("Could not create schema_migrations: {}", error))?;
+
+    connection
+        .execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK])
+        .map_err(|error| format!("Could not lock migrations: {}", error))?;
+    let result = apply_pending(connection);
+    if let Err(error) = connection.execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK]) {
+        error!("error unlocking migrations. Error: {}", error);
+    }
+    result
+}
+
+fn apply_pending(connection: &Connection) -> Result<usize, String> {
+    let applied: Vec<i32> = connection
+        .query("SELECT version FROM schema_migrations", &[])
+        .map_err(|error| format!("Could not read schema_migrations: {}", error))?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let mut count = 0;
+    for (version, name, sql) in MIGRATIONS.iter() {
+        if applied.contains(version) {
+            continue;
+        }
+
+        let failed =
+            |error: postgres::Error| format!("Migration {} {} failed: {}", version, name, error);
+        let transaction = connection.transaction().map_err(failed)?;
+        transaction.batch_execute(sql).map_err(failed)?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[version, name],
+            )
+            .map_err(failed)?;
+        transaction.commit().map_err(failed)?;
+
+        info!("Applied migration {} {}", version, name);
+        count += 1;
+    }
+    Ok(count)
+}
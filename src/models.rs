@@ -6,7 +6,11 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use chrono::NaiveDate;
+use crate::description::DescriptionFormat;
+use crate::titles::TitleLanguage;
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::http::RawStr;
+use rocket::request::FromFormValue;
 use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +20,18 @@ pub struct User {
     pub name: String,
     pub avatar_s3: String,
     pub avatar_anilist: String,
+    pub provider: String,
+    pub external_id: Option<i32>,
+}
+
+// Kept apart from `User` since it is only written by syncs, never by saving a profile.
+#[derive(Debug, Clone)]
+pub struct SyncStatus {
+    pub user_id: i32,
+    pub last_synced_at: Option<DateTime<Utc>>,
+    pub last_sync_status: Option<String>,
+    /// In milliseconds.
+    pub last_sync_duration: Option<i32>,
 }
 
 #[derive(Debug, Clone)]
@@ -29,6 +45,14 @@ pub struct Anime {
     pub native: Option<String>,
     pub romaji: Option<String>,
     pub english: Option<String>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
+    pub mal_id: Option<i32>,
+    /// Missing for anime stored before descriptions had plain text versions, until the server
+    /// fills them in.
+    pub description_text: Option<String>,
+    pub description_summary: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -40,6 +64,10 @@ pub struct ListItem {
     pub start_day: Option<NaiveDate>,
     pub end_day: Option<NaiveDate>,
     pub score: Option<i16>,
+    pub status: Option<String>,
+    pub progress: Option<i32>,
+    /// When the entry disappeared from the user's provider, if it has.
+    pub removed_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone)]
@@ -59,20 +87,217 @@ pub struct RestResponse {
 pub struct ResponseList {
     pub id: String,
     pub avatar: String,
+    pub last_synced_at: Option<DateTime<Utc>>,
     pub list: Vec<ResponseItem>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ResponseItem {
+    /// The title in the language the viewer asked for.
+    pub display_title: String,
     pub user_title: Option<String>,
     pub start_day: Option<NaiveDate>,
     pub end_day: Option<NaiveDate>,
     pub score: Option<i16>,
+    pub status: Option<String>,
+    pub progress: Option<i32>,
     pub average: Option<i16>,
     pub native: Option<String>,
     pub romaji: Option<String>,
     pub english: Option<String>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
+    /// In the form the viewer asked for, or missing when they asked for none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub cover: String,
+    pub id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed_at: Option<DateTime<Utc>>,
+}
+
+/// Which version of a user's list to get.
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    /// The list as it was at the end of this day instead of the current one.
+    pub as_of: Option<NaiveDate>,
+    /// Whether to include entries that were removed but not yet purged.
+    pub include_removed: bool,
+    /// Which title to show as each entry's `display_title`.
+    pub title: TitleLanguage,
+    pub description: DescriptionFormat,
+    /// The only fields of each entry to read and send, or `None` for all of them.
+    pub fields: Option<Vec<&'static str>>,
+}
+
+#[derive(Debug, Clone)]
+//#[table_name = "list_changes"]
+pub struct ListChange {
+    pub run_id: i32,
+    pub user_id: i32,
+    pub anime_id: i32,
+    pub changed_at: DateTime<Utc>,
+    /// One of `added`, `updated` or `removed`.
+    pub change: String,
+    /// The changed column for updates.
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+/// What a sync changed, recorded in its sync run.
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    pub added: i32,
+    pub updated: i32,
+    pub removed: i32,
+    pub images_uploaded: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangesResponse {
+    pub id: String,
+    pub changes: Vec<ChangeItem>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeItem {
+    pub id: i32,
+    pub user_title: Option<String>,
+    pub native: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub changed_at: DateTime<Utc>,
+    pub change: String,
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HistoryResponse {
+    pub id: String,
+    pub anime_id: i32,
+    pub history: Vec<HistoryItem>,
+}
+
+/// One version of a list entry, current when `valid_to` is missing.
+#[derive(Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: Option<DateTime<Utc>>,
+    pub user_title: Option<String>,
+    pub start_day: Option<NaiveDate>,
+    pub end_day: Option<NaiveDate>,
+    pub score: Option<i16>,
+    pub status: Option<String>,
+    pub progress: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResponse {
+    pub id: String,
+    pub q: String,
+    pub results: Vec<SearchResult>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchResult {
+    pub id: i32,
+    pub user_title: Option<String>,
+    pub native: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub status: Option<String>,
+    pub score: Option<i16>,
+    pub start_day: Option<NaiveDate>,
+    pub end_day: Option<NaiveDate>,
+    pub cover: String,
+    /// How well the entry matches, higher first.
+    pub rank: f32,
+    pub highlights: TitleHighlights,
+}
+
+/// An entry's titles as HTML, with the words that matched the search wrapped in `<mark>`.
+#[derive(Serialize, Deserialize)]
+pub struct TitleHighlights {
+    pub user_title: Option<String>,
+    pub native: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+}
+
+/// An anime along with how the users tracking it rated it.
+#[derive(Serialize, Deserialize)]
+pub struct AnimeResponse {
+    pub id: i32,
+    pub mal_id: Option<i32>,
+    pub native: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
     pub description: String,
     pub cover: String,
+    /// AniList's average score, out of 100.
+    pub average: Option<i16>,
+    /// How many users have it on their list.
+    pub tracked: i64,
+    /// How many of them completed it.
+    pub completed: i64,
+    /// The average score out of 100 of the users who completed and scored it.
+    pub completed_average: Option<f64>,
+    /// How far `completed_average` is above AniList's average.
+    pub average_difference: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnimeSearchResponse {
+    pub search: String,
+    pub results: Vec<AnimeSummary>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnimeSummary {
     pub id: i32,
+    pub native: Option<String>,
+    pub romaji: Option<String>,
+    pub english: Option<String>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub cover: String,
+    pub average: Option<i16>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportReport {
+    pub user: String,
+    /// Only sent when the import made the user. It has to be sent again as a bearer token to
+    /// import into their list or restore their entries later.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub imported: usize,
+    pub skipped: usize,
+    pub unmapped: Vec<UnmappedEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnmappedEntry {
+    pub mal_id: i32,
+    pub title: String,
+}
+
+/// A `YYYY-MM-DD` date taken from a query string.
+pub struct QueryDate(pub NaiveDate);
+
+impl<'v> FromFormValue<'v> for QueryDate {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<QueryDate, &'v RawStr> {
+        NaiveDate::parse_from_str(form_value.as_str(), "%Y-%m-%d")
+            .map(QueryDate)
+            .map_err(|_| form_value)
+    }
 }
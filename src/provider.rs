@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::anilist_query::AniList;
+use crate::kitsu_query::Kitsu;
+use chrono::NaiveDate;
+
+/// A source of users' anime lists. Syncing and storage only go through this trait, so adding
+/// another tracker means implementing it rather than touching the database code.
+pub trait ListProvider {
+    /// The name stored alongside the provider's users, also used as their username prefix.
+    fn name(&self) -> &'static str;
+
+    /// Looks up a user's profile by the name they use on the provider.
+    fn get_user(&self, username: &str) -> Option<ProviderUser>;
+
+    /// Gets the entries of a user's list that should be stored: the ones they are watching or
+    /// have completed. `None` means the list could not be fetched, as opposed to being empty.
+    fn get_entries(&self, user_id: i32) -> Option<Vec<ProviderEntry>>;
+
+    /// Roughly how many rate limited requests syncing a list of this many entries takes, so
+    /// background syncs can stay within the scheduler's request budget.
+    fn requests_for(&self, entries: usize) -> usize;
+
+    /// Whether the provider's users are stored under their id there instead of a local one. Only
+    /// safe for one provider, since ids from different providers would collide.
+    fn stores_external_id_as_user_id(&self) -> bool {
+        false
+    }
+}
+
+// Users are stored under one of these providers. Local users were imported from elsewhere and have
+// no provider to sync from.
+static PROVIDERS: [&str; 3] = ["anilist", "kitsu", "local"];
+
+/// Splits a username such as `kitsu:<name>` into the provider it belongs to and the name used
+/// there. Names without a known prefix are AniList users.
+pub fn split_username(username: &str) -> (&str, &str) {
+    match username.find(':') {
+        Some(index) if PROVIDERS.contains(&&username[..index]) => {
+            (&username[..index], &username[index + 1..])
+        }
+        _ => ("anilist", username),
+    }
+}
+
+/// The username a provider's user is known by here, the inverse of `split_username`.
+pub fn qualified_username(provider: &str, name: &str) -> String {
+    if provider == "anilist" {
+        name.to_owned()
+    } else {
+        format!("{}:{}", provider, name)
+    }
+}
+
+/// Picks the provider to sync a username from, returning it along with the name to look up there.
+pub fn for_username(username: &str) -> Option<(Box<dyn ListProvider + Send>, &str)> {
+    let (provider_name, name) = split_username(username);
+    for_name(provider_name).map(|provider| (provider, name))
+}
+
+/// The provider stored users of this name are synced from. Local users have none.
+pub fn for_name(provider_name: &str) -> Option<Box<dyn ListProvider + Send>> {
+    match provider_name {
+        "anilist" => Some(Box::new(AniList)),
+        "kitsu" => Some(Box::new(Kitsu)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderUser {
+    pub id: i32,
+    pub name: String,
+    pub avatar: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderEntry {
+    pub media: ProviderMedia,
+    pub status: Option<String>,
+    pub progress: Option<i32>,
+    /// Out of 100, with 0 meaning unscored.
+    pub score: Option<i16>,
+    pub start_day: Option<NaiveDate>,
+    pub end_day: Option<NaiveDate>,
+}
+
+/// An anime, identified by its AniList id since that is what the `anime` table is keyed by.
+#[derive(Debug, Clone)]
+pub struct ProviderMedia {
+    pub id: i32,
+    pub mal_id: Option<i32>,
+    pub user_title: Option<String>,
+    pub english: Option<String>,
+    pub romaji: Option<String>,
+    pub native: Option<String>,
+    pub description: String,
+    pub cover: String,
+    pub average: Option<i16>,
+    pub format: Option<String>,
+    pub episodes: Option<i32>,
+    pub duration: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_known_prefixes() {
+        assert_eq!(split_username("kitsu:Foo"), ("kitsu", "Foo"));
+        assert_eq!(split_username("local:Foo"), ("local", "Foo"));
+        assert_eq!(split_username("anilist:Foo"), ("anilist", "Foo"));
+    }
+
+    #[test]
+    fn treats_other_names_as_anilist() {
+        assert_eq!(split_username("Foo"), ("anilist", "Foo"));
+        assert_eq!(split_username("mal:Foo"), ("anilist", "mal:Foo"));
+        assert_eq!(split_username(""), ("anilist", ""));
+    }
+
+    #[test]
+    fn qualifies_all_but_anilist_names() {
+        assert_eq!(qualified_username("anilist", "Foo"), "Foo");
+        assert_eq!(qualified_username("kitsu", "Foo"), "kitsu:Foo");
+        let (provider, name) = split_username("kitsu:a:b");
+        assert_eq!(qualified_username(provider, name), "kitsu:a:b");
+    }
+}
@@ -0,0 +1,960 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// All of the SQL for users, anime and lists. Rows are read by column name into the models, so
+// queries can select columns in any order, and errors are left to the caller to log.
+
+use crate::models;
+use chrono::{DateTime, TimeZone, Utc};
+use postgres::rows::{Row, Rows};
+use postgres::types::{FromSql, ToSql};
+use postgres::{GenericConnection, Result};
+
+/// A model stored as one row of a table, with a column for each of its fields.
+pub trait Record: Sized {
+    const TABLE: &'static str;
+    const KEY: &'static [&'static str];
+    const COLUMNS: &'static [&'static str];
+    /// The Postgres type of each column, in the order of `COLUMNS`.
+    const TYPES: &'static [&'static str];
+
+    fn from_row(row: &Row) -> Self;
+
+    /// The model's values in the order of `COLUMNS`.
+    fn params(&self) -> Vec<&dyn ToSql>;
+
+    /// An array per column holding that column's value for each record, for use with `UNNEST`.
+    fn arrays(records: &[Self]) -> Vec<Box<dyn ToSql>>;
+}
+
+/// A model that can be read from a row holding only some of its columns, for models whose fields
+/// all have an empty value.
+pub trait PartialRecord: Record {
+    /// Like `from_row`, but leaves the fields of missing columns empty.
+    fn from_partial_row(row: &Row) -> Self;
+}
+
+// The column list, the row mapping and the parameters all come from one list of fields. Leaving a
+// field out is a missing field in the struct literal, so the column lists can't drift from the
+// models without failing to compile.
+macro_rules! record {
+    (partial $model:path, $table:expr, [$($key:ident),*], { $($field:ident: $type:expr),* $(,)? }) => {
+        record!($model, $table, [$($key),*], { $($field: $type),* });
+
+        impl PartialRecord for $model {
+            fn from_partial_row(row: &Row) -> Self {
+                $model {
+                    $($field: row
+                        .get_opt(stringify!($field))
+                        .map(|value| value.unwrap())
+                        .unwrap_or_default()),*
+                }
+            }
+        }
+    };
+    ($model:path, $table:expr, [$($key:ident),*], { $($field:ident: $type:expr),* $(,)? }) => {
+        impl Record for $model {
+            const TABLE: &'static str = $table;
+            const KEY: &'static [&'static str] = &[$(stringify!($key)),*];
+            const COLUMNS: &'static [&'static str] = &[$(stringify!($field)),*];
+            const TYPES: &'static [&'static str] = &[$($type),*];
+
+            fn from_row(row: &Row) -> Self {
+                $model {
+                    $($field: row.get(stringify!($field))),*
+                }
+            }
+
+            fn params(&self) -> Vec<&dyn ToSql> {
+                vec![$(&self.$field),*]
+            }
+
+            fn arrays(records: &[Self]) -> Vec<Box<dyn ToSql>> {
+                vec![$(
+                    Box::new(records.iter().map(|record| record.$field.clone()).collect::<Vec<_>>())
+                ),*]
+            }
+        }
+    };
+}
+
+record!(models::User, "users", [user_id], {
+    user_id: "int4",
+    name: "text",
+    avatar_s3: "text",
+    avatar_anilist: "text",
+    provider: "text",
+    external_id: "int4",
+});
+
+record!(partial models::Anime, "anime", [anime_id], {
+    anime_id: "int4",
+    description: "text",
+    cover_s3: "text",
+    cover_anilist: "text",
+    average: "int2",
+    native: "text",
+    romaji: "text",
+    english: "text",
+    format: "text",
+    episodes: "int4",
+    duration: "int4",
+    mal_id: "int4",
+    description_text: "text",
+    description_summary: "text",
+});
+
+record!(partial models::ListItem, "lists", [user_id, anime_id], {
+    user_id: "int4",
+    anime_id: "int4",
+    user_title: "text",
+    start_day: "date",
+    end_day: "date",
+    score: "int2",
+    status: "text",
+    progress: "int4",
+    removed_at: "timestamptz",
+});
+
+// Changes are only ever inserted, so they have no key to upsert on.
+record!(models::ListChange, "list_changes", [], {
+    run_id: "int4",
+    user_id: "int4",
+    anime_id: "int4",
+    changed_at: "timestamptz",
+    change: "text",
+    field: "text",
+    old_value: "text",
+    new_value: "text",
+});
+
+/// A model's columns qualified with a table alias, for use in a SELECT.
+pub fn columns<T: Record>(alias: &str) -> String {
+    T::COLUMNS
+        .iter()
+        .map(|column| format!("{}.{}", alias, column))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Like `columns`, but limited to the model's key and `only` when it is given.
+fn some_columns<T: Record>(alias: &str, only: Option<&[&str]>) -> String {
+    T::COLUMNS
+        .iter()
+        .filter(|column| {
+            only.map_or(true, |only| {
+                T::KEY.contains(column) || only.contains(column)
+            })
+        })
+        .map(|column| format!("{}.{}", alias, column))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// The expressions selected for `columns` that are in `only`, or all of them, named after their
+// columns. The first column is the key and always selected.
+fn select_list(columns: &[(&str, &str)], only: Option<&[&str]>) -> String {
+    columns
+        .iter()
+        .enumerate()
+        .filter(|(index, (column, _))| {
+            *index == 0 || only.map_or(true, |only| only.contains(column))
+        })
+        .map(|(_, (column, expression))| format!("{} as {}", expression, column))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// Reads a column that may not have been selected, leaving it empty when it wasn't.
+fn get_or_default<T: FromSql + Default>(row: &Row, column: &str) -> T {
+    row.get_opt(column)
+        .map(|value| value.unwrap())
+        .unwrap_or_default()
+}
+
+/// Inserts a model, or updates every column but its key when it is already stored.
+pub fn upsert<T: Record>(record: &T, connection: &dyn GenericConnection) -> Result<u64> {
+    let placeholders: Vec<String> = (1..=T::COLUMNS.len()).map(|i| format!("${}", i)).collect();
+    let sql = format!(
+        "INSERT INTO {} ({}) VALUES ({}) {}",
+        T::TABLE,
+        T::COLUMNS.join(", "),
+        placeholders.join(", "),
+        on_conflict_update::<T>()
+    );
+
+    connection.prepare_cached(&sql)?.execute(&record.params())
+}
+
+/// Upserts many models in one statement by passing each column as an array. Keys must be unique
+/// within `records`, since Postgres won't update the same row twice in one statement.
+pub fn upsert_all<T: Record>(records: &[T], connection: &dyn GenericConnection) -> Result<u64> {
+    let sql = format!("{} {}", insert_unnest::<T>(), on_conflict_update::<T>());
+    execute_unnest(&sql, records, connection)
+}
+
+/// Inserts many models in one statement, for tables that only ever have rows added.
+pub fn insert_all<T: Record>(records: &[T], connection: &dyn GenericConnection) -> Result<u64> {
+    execute_unnest(&insert_unnest::<T>(), records, connection)
+}
+
+fn insert_unnest<T: Record>() -> String {
+    let arrays: Vec<String> = T::TYPES
+        .iter()
+        .enumerate()
+        .map(|(i, column_type)| format!("${}::{}[]", i + 1, column_type))
+        .collect();
+    format!(
+        "INSERT INTO {} ({}) SELECT * FROM UNNEST({})",
+        T::TABLE,
+        T::COLUMNS.join(", "),
+        arrays.join(", ")
+    )
+}
+
+fn execute_unnest<T: Record>(
+    sql: &str,
+    records: &[T],
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    let arrays = T::arrays(records);
+    let params: Vec<&dyn ToSql> = arrays.iter().map(|array| array.as_ref()).collect();
+    connection.prepare_cached(sql)?.execute(&params)
+}
+
+fn on_conflict_update<T: Record>() -> String {
+    let updates: Vec<String> = T::COLUMNS
+        .iter()
+        .filter(|column| !T::KEY.contains(column))
+        .map(|column| format!("{0} = excluded.{0}", column))
+        .collect();
+    format!(
+        "ON CONFLICT ({}) DO UPDATE SET {}",
+        T::KEY.join(", "),
+        updates.join(", ")
+    )
+}
+
+// Lists
+
+/// Every row of a user's list along with the user and anime it belongs to. Names are matched
+/// regardless of case. Given `only`, just those anime and list columns are read.
+pub fn find_list(
+    provider: &str,
+    name: &str,
+    include_removed: bool,
+    only: Option<&[&str]>,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::ListItemMap>> {
+    let sql = format!(
+        "SELECT {}, {}, {} FROM lists as l INNER JOIN users as u ON l.user_id = u.user_id \
+         INNER JOIN anime as a ON l.anime_id = a.anime_id \
+         WHERE u.provider = $1 AND lower(u.name) = lower($2) AND ($3 OR l.removed_at IS NULL)",
+        columns::<models::User>("u"),
+        some_columns::<models::Anime>("a", only),
+        some_columns::<models::ListItem>("l", only)
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&provider, &name, &include_removed])?;
+
+    Ok(list_item_maps(&rows, only.is_some()))
+}
+
+/// A user's list as it was at a point in time, rebuilt from its history. Anime details are the
+/// current ones since those aren't versioned, and removed entries have no history.
+pub fn find_list_as_of(
+    provider: &str,
+    name: &str,
+    at: DateTime<Utc>,
+    only: Option<&[&str]>,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::ListItemMap>> {
+    let sql = format!(
+        "SELECT {}, {}, {} FROM (SELECT h.*, NULL::timestamptz as removed_at FROM list_history as h) \
+         as l INNER JOIN users as u ON l.user_id = u.user_id \
+         INNER JOIN anime as a ON l.anime_id = a.anime_id \
+         WHERE u.provider = $1 AND lower(u.name) = lower($2) \
+         AND l.valid_from <= $3 AND (l.valid_to IS NULL OR l.valid_to > $3)",
+        columns::<models::User>("u"),
+        some_columns::<models::Anime>("a", only),
+        some_columns::<models::ListItem>("l", only)
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&provider, &name, &at])?;
+
+    Ok(list_item_maps(&rows, only.is_some()))
+}
+
+fn list_item_maps(rows: &Rows, partial: bool) -> Vec<models::ListItemMap> {
+    rows.iter()
+        .map(|row| models::ListItemMap {
+            user: models::User::from_row(&row),
+            anime: if partial {
+                models::Anime::from_partial_row(&row)
+            } else {
+                models::Anime::from_row(&row)
+            },
+            list_item: if partial {
+                models::ListItem::from_partial_row(&row)
+            } else {
+                models::ListItem::from_row(&row)
+            },
+        })
+        .collect()
+}
+
+pub fn find_list_items(
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::ListItem>> {
+    let sql = format!(
+        "SELECT {} FROM lists as l WHERE l.user_id = $1 AND l.removed_at IS NULL",
+        columns::<models::ListItem>("l")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&user_id])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::ListItem::from_row(&row))
+        .collect())
+}
+
+/// The rows of a user's list that were removed and not yet purged.
+pub fn find_removed_list_items(
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::ListItem>> {
+    let sql = format!(
+        "SELECT {} FROM lists as l WHERE l.user_id = $1 AND l.removed_at IS NOT NULL",
+        columns::<models::ListItem>("l")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&user_id])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::ListItem::from_row(&row))
+        .collect())
+}
+
+/// Marks every row of a user's list apart from the given anime as removed. Rows that were already
+/// removed keep their original time.
+pub fn remove_list_items_except(
+    user_id: i32,
+    anime_ids: &[i32],
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached(
+            "UPDATE lists SET removed_at = now() \
+             WHERE user_id = $1 AND removed_at IS NULL AND anime_id <> ALL($2)",
+        )?
+        .execute(&[&user_id, &anime_ids.to_vec()])
+}
+
+/// Puts a removed row back on a user's list, returning whether there was one to restore.
+pub fn restore_list_item(
+    user_id: i32,
+    anime_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<bool> {
+    let restored = connection
+        .prepare_cached(
+            "UPDATE lists SET removed_at = NULL \
+             WHERE user_id = $1 AND anime_id = $2 AND removed_at IS NOT NULL",
+        )?
+        .execute(&[&user_id, &anime_id])?;
+
+    Ok(restored > 0)
+}
+
+/// Deletes rows that were removed before `cutoff`, returning how many there were.
+pub fn purge_removed(cutoff: DateTime<Utc>, connection: &dyn GenericConnection) -> Result<u64> {
+    connection
+        .prepare_cached("DELETE FROM lists WHERE removed_at < $1")?
+        .execute(&[&cutoff])
+}
+
+// The columns `find_changes` can read, as the expression each is selected with.
+static CHANGE_COLUMNS: [(&str, &str); 10] = [
+    ("anime_id", "c.anime_id"),
+    ("user_title", "l.user_title"),
+    ("native", "a.native"),
+    ("romaji", "a.romaji"),
+    ("english", "a.english"),
+    ("changed_at", "c.changed_at"),
+    ("change", "c.change"),
+    ("field", "c.field"),
+    ("old_value", "c.old_value"),
+    ("new_value", "c.new_value"),
+];
+
+/// The most recent changes to a user's list along with the anime they were made to. Given
+/// `only`, just those columns are read.
+pub fn find_changes(
+    user_id: i32,
+    limit: i64,
+    only: Option<&[&str]>,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::ChangeItem>> {
+    let sql = format!(
+        "SELECT {} FROM list_changes as c \
+         INNER JOIN anime as a ON c.anime_id = a.anime_id \
+         LEFT JOIN lists as l ON c.user_id = l.user_id AND c.anime_id = l.anime_id \
+         WHERE c.user_id = $1 ORDER BY c.changed_at DESC, c.change_id LIMIT $2",
+        select_list(&CHANGE_COLUMNS, only)
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&user_id, &limit])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::ChangeItem {
+            id: row.get("anime_id"),
+            user_title: get_or_default(&row, "user_title"),
+            native: get_or_default(&row, "native"),
+            romaji: get_or_default(&row, "romaji"),
+            english: get_or_default(&row, "english"),
+            // Left at the epoch when it wasn't selected, since it isn't sent then.
+            changed_at: row
+                .get_opt("changed_at")
+                .map(|value| value.unwrap())
+                .unwrap_or_else(|| Utc.timestamp_opt(0, 0).unwrap()),
+            change: get_or_default(&row, "change"),
+            field: get_or_default(&row, "field"),
+            old_value: get_or_default(&row, "old_value"),
+            new_value: get_or_default(&row, "new_value"),
+        })
+        .collect())
+}
+
+/// Every version of a user's entry for an anime, oldest first.
+pub fn find_history(
+    user_id: i32,
+    anime_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::HistoryItem>> {
+    let rows = connection
+        .prepare_cached(
+            "SELECT valid_from, valid_to, user_title, start_day, end_day, score, status, progress \
+             FROM list_history WHERE user_id = $1 AND anime_id = $2 \
+             ORDER BY valid_from, history_id",
+        )?
+        .query(&[&user_id, &anime_id])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::HistoryItem {
+            valid_from: row.get("valid_from"),
+            valid_to: row.get("valid_to"),
+            user_title: row.get("user_title"),
+            start_day: row.get("start_day"),
+            end_day: row.get("end_day"),
+            score: row.get("score"),
+            status: row.get("status"),
+            progress: row.get("progress"),
+        })
+        .collect())
+}
+
+// How well an entry matches a search, which results are ordered by.
+const SEARCH_RANK: &str = "ts_rank(to_tsvector('simple', coalesce(l.user_title, '')) \
+    || to_tsvector('simple', anime_titles(a.romaji, a.english, a.native)), q.query) \
+    + greatest(word_similarity(q.search, coalesce(l.user_title, '')), \
+    word_similarity(q.search, anime_titles(a.romaji, a.english, a.native)))";
+
+// The columns `search_list` can read, as the expression each is selected with.
+static SEARCH_COLUMNS: [(&str, &str); 15] = [
+    ("anime_id", "l.anime_id"),
+    ("user_title", "l.user_title"),
+    ("native", "a.native"),
+    ("romaji", "a.romaji"),
+    ("english", "a.english"),
+    ("status", "l.status"),
+    ("score", "l.score"),
+    ("start_day", "l.start_day"),
+    ("end_day", "l.end_day"),
+    ("cover_s3", "a.cover_s3"),
+    ("rank", SEARCH_RANK),
+    (
+        "user_title_highlight",
+        "ts_headline('simple', l.user_title, q.query, q.options)",
+    ),
+    (
+        "native_highlight",
+        "ts_headline('simple', a.native, q.query, q.options)",
+    ),
+    (
+        "romaji_highlight",
+        "ts_headline('simple', a.romaji, q.query, q.options)",
+    ),
+    (
+        "english_highlight",
+        "ts_headline('simple', a.english, q.query, q.options)",
+    ),
+];
+
+/// Entries on a user's list whose titles match a search, most relevant first. Whole words are
+/// matched by full-text search and misspelled or partial ones by trigram similarity. Neither
+/// splits text written without spaces, such as Japanese: the `simple` parser reads a whole title as
+/// one word, and whether pg_trgm sees its characters at all depends on the database's locale. So
+/// any title containing the search is matched too, ranked after the others and left unhighlighted.
+/// Highlights wrap the matched words in `HIGHLIGHT_START` and `HIGHLIGHT_STOP` and aren't escaped.
+/// Given `only`, just those columns are read.
+pub fn search_list(
+    provider: &str,
+    name: &str,
+    search: &str,
+    limit: i64,
+    only: Option<&[&str]>,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::SearchResult>> {
+    let sql = format!(
+        "WITH q AS (SELECT $3::text as search, \
+            plainto_tsquery('simple', $3) || plainto_tsquery('english', $3) as query, \
+            'HighlightAll=true, StartSel=' || chr(2) || ', StopSel=' || chr(3) as options) \
+         SELECT {} FROM lists as l INNER JOIN users as u ON l.user_id = u.user_id \
+         INNER JOIN anime as a ON l.anime_id = a.anime_id CROSS JOIN q \
+         WHERE u.provider = $1 AND lower(u.name) = lower($2) AND l.removed_at IS NULL \
+         AND (to_tsvector('simple', coalesce(l.user_title, '')) @@ q.query \
+            OR to_tsvector('simple', anime_titles(a.romaji, a.english, a.native)) @@ q.query \
+            OR q.search <% l.user_title \
+            OR q.search <% anime_titles(a.romaji, a.english, a.native) \
+            OR strpos(lower(coalesce(l.user_title, '') || ' ' \
+                || anime_titles(a.romaji, a.english, a.native)), lower(q.search)) > 0) \
+         ORDER BY {} DESC, l.anime_id LIMIT $4",
+        select_list(&SEARCH_COLUMNS, only),
+        SEARCH_RANK
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&provider, &name, &search, &limit])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::SearchResult {
+            id: row.get("anime_id"),
+            user_title: get_or_default(&row, "user_title"),
+            native: get_or_default(&row, "native"),
+            romaji: get_or_default(&row, "romaji"),
+            english: get_or_default(&row, "english"),
+            status: get_or_default(&row, "status"),
+            score: get_or_default(&row, "score"),
+            start_day: get_or_default(&row, "start_day"),
+            end_day: get_or_default(&row, "end_day"),
+            cover: get_or_default(&row, "cover_s3"),
+            rank: get_or_default(&row, "rank"),
+            highlights: models::TitleHighlights {
+                user_title: get_or_default(&row, "user_title_highlight"),
+                native: get_or_default(&row, "native_highlight"),
+                romaji: get_or_default(&row, "romaji_highlight"),
+                english: get_or_default(&row, "english_highlight"),
+            },
+        })
+        .collect())
+}
+
+/// Marks the start of a matched word in `search_list` highlights.
+pub static HIGHLIGHT_START: char = '\u{2}';
+/// Marks the end of a matched word in `search_list` highlights.
+pub static HIGHLIGHT_STOP: char = '\u{3}';
+
+// Anime
+
+// Matches the expression the `anime_search` index is built on.
+static ANIME_DOCUMENT: &str = "anime_document(a.romaji, a.english, a.native, a.description)";
+
+pub fn find_anime(
+    anime_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Option<models::Anime>> {
+    let sql = format!(
+        "SELECT {} FROM anime as a WHERE a.anime_id = $1",
+        columns::<models::Anime>("a")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&anime_id])?;
+
+    Ok(rows.iter().next().map(|row| models::Anime::from_row(&row)))
+}
+
+/// Anime stored before descriptions had plain text versions.
+pub fn find_anime_without_description_text(
+    limit: i64,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::Anime>> {
+    let sql = format!(
+        "SELECT {} FROM anime as a WHERE a.description_text IS NULL ORDER BY a.anime_id LIMIT $1",
+        columns::<models::Anime>("a")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&limit])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::Anime::from_row(&row))
+        .collect())
+}
+
+/// How many users have an anime on their list, how many of them completed it and their average
+/// score out of 100, leaving out unscored entries. Older rows without a status count as completed
+/// when they have an end date.
+pub fn find_anime_stats(
+    anime_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<(i64, i64, Option<f64>)> {
+    let rows = connection
+        .prepare_cached(
+            "SELECT count(*) as tracked, count(*) FILTER (WHERE completed) as completed, \
+             avg(score) FILTER (WHERE completed AND score > 0)::float8 as completed_average \
+             FROM (SELECT score, coalesce(status = 'COMPLETED', end_day IS NOT NULL) as completed \
+             FROM lists WHERE anime_id = $1 AND removed_at IS NULL) as l",
+        )?
+        .query(&[&anime_id])?;
+    let row = rows.get(0);
+
+    Ok((
+        row.get("tracked"),
+        row.get("completed"),
+        row.get("completed_average"),
+    ))
+}
+
+/// Anime whose titles or description match a search, most relevant first. Title matches rank
+/// above description ones.
+pub fn search_anime(
+    query: &str,
+    limit: i64,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<models::Anime>> {
+    let sql = format!(
+        "SELECT {columns} FROM anime as a, \
+         (SELECT plainto_tsquery('simple', $1) || plainto_tsquery('english', $1) as query) as q \
+         WHERE {document} @@ q.query \
+         ORDER BY ts_rank({document}, q.query) DESC, a.anime_id LIMIT $2",
+        columns = columns::<models::Anime>("a"),
+        document = ANIME_DOCUMENT
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&query, &limit])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| models::Anime::from_row(&row))
+        .collect())
+}
+
+// Sync runs
+
+/// Records that a sync has started, returning the id of its run. This happens outside of the
+/// sync's transaction so failed syncs are recorded too.
+pub fn start_sync_run(user_id: i32, connection: &dyn GenericConnection) -> Result<i32> {
+    let rows = connection
+        .prepare_cached("INSERT INTO sync_runs (user_id) VALUES ($1) RETURNING run_id")?
+        .query(&[&user_id])?;
+
+    Ok(rows.get(0).get(0))
+}
+
+pub fn finish_sync_run(
+    run_id: i32,
+    summary: &models::SyncSummary,
+    error: Option<&str>,
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached(
+            "UPDATE sync_runs SET finished_at = now(), added = $2, updated = $3, removed = $4, \
+             images_uploaded = $5, error = $6 WHERE run_id = $1",
+        )?
+        .execute(&[
+            &run_id,
+            &summary.added,
+            &summary.updated,
+            &summary.removed,
+            &summary.images_uploaded,
+            &error,
+        ])
+}
+
+/// Records how many images a finished sync run uploaded, which is only known after the run.
+pub fn set_images_uploaded(
+    run_id: i32,
+    images_uploaded: i32,
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached("UPDATE sync_runs SET images_uploaded = $2 WHERE run_id = $1")?
+        .execute(&[&run_id, &images_uploaded])
+}
+
+// Users
+
+// Syncs lock users with the two key form of advisory locks, which never overlaps the single key
+// form used by migrations.
+static SYNC_LOCK: i32 = 1;
+
+/// Waits for any other connection syncing a user, then holds their lock until the connection is
+/// closed.
+pub fn lock_user(user_id: i32, connection: &dyn GenericConnection) -> Result<()> {
+    connection
+        .prepare_cached("SELECT pg_advisory_lock($1, $2)")?
+        .query(&[&SYNC_LOCK, &user_id])?;
+    Ok(())
+}
+
+/// The user with this id on a provider. Users stored before their provider's ids were are matched
+/// by name instead, so their next sync links them up.
+pub fn find_user(
+    provider: &str,
+    external_id: i32,
+    name: &str,
+    connection: &dyn GenericConnection,
+) -> Result<Option<models::User>> {
+    let sql = format!(
+        "SELECT {} FROM users as u WHERE u.provider = $1 AND (u.external_id = $2 \
+         OR (u.external_id IS NULL AND lower(u.name) = lower($3))) \
+         ORDER BY u.external_id NULLS LAST LIMIT 1",
+        columns::<models::User>("u")
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&provider, &external_id, &name])?;
+
+    Ok(rows.iter().next().map(|row| models::User::from_row(&row)))
+}
+
+pub fn find_local_user(
+    name: &str,
+    connection: &dyn GenericConnection,
+) -> Result<Option<models::User>> {
+    let sql = format!(
+        "SELECT {} FROM users as u WHERE u.provider = 'local' AND lower(u.name) = lower($1)",
+        columns::<models::User>("u")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&name])?;
+
+    Ok(rows.iter().next().map(|row| models::User::from_row(&row)))
+}
+
+/// Replaces the hash of the token that proves ownership of a user.
+pub fn set_user_token(
+    user_id: i32,
+    token: &str,
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached("UPDATE users SET token_hash = sha256($2) WHERE user_id = $1")?
+        .execute(&[&user_id, &token.as_bytes()])
+}
+
+/// Whether a token is the one given out for a user. Users who were never given one can't be
+/// proven to be owned by anyone.
+pub fn check_user_token(
+    user_id: i32,
+    token: &str,
+    connection: &dyn GenericConnection,
+) -> Result<bool> {
+    let rows = connection
+        .prepare_cached(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE user_id = $1 AND token_hash = sha256($2))",
+        )?
+        .query(&[&user_id, &token.as_bytes()])?;
+
+    Ok(rows.get(0).get(0))
+}
+
+/// The user going by a name in any case, or else the one who most recently used it before being
+/// renamed.
+pub fn find_user_by_name(
+    provider: &str,
+    name: &str,
+    connection: &dyn GenericConnection,
+) -> Result<Option<models::User>> {
+    // Each branch is looked up through its own `(provider, lower(name))` index.
+    let sql = format!(
+        "SELECT {columns} FROM (\
+            SELECT {users}, 0 AS rank, now() AS renamed_at FROM users as u \
+            WHERE u.provider = $1 AND lower(u.name) = lower($2) \
+            UNION ALL SELECT {users}, 1 AS rank, a.renamed_at FROM user_aliases as a \
+            INNER JOIN users as u ON a.user_id = u.user_id \
+            WHERE a.provider = $1 AND lower(a.name) = lower($2)\
+         ) as m ORDER BY rank, renamed_at DESC LIMIT 1",
+        columns = columns::<models::User>("m"),
+        users = columns::<models::User>("u")
+    );
+    let rows = connection
+        .prepare_cached(&sql)?
+        .query(&[&provider, &name])?;
+
+    Ok(rows.iter().next().map(|row| models::User::from_row(&row)))
+}
+
+/// When an update was last asked for under a name. Names are matched regardless of case.
+pub fn find_update_attempt(
+    provider: &str,
+    name: &str,
+    connection: &dyn GenericConnection,
+) -> Result<Option<DateTime<Utc>>> {
+    let rows = connection
+        .prepare_cached(
+            "SELECT attempted_at FROM update_attempts WHERE provider = $1 AND name = lower($2)",
+        )?
+        .query(&[&provider, &name])?;
+
+    Ok(rows.iter().next().map(|row| row.get("attempted_at")))
+}
+
+/// Records that an update was asked for under a name, unless one already was after `since`.
+/// Returns whether it was recorded, so only one of several requests at once goes ahead.
+pub fn claim_update_attempt(
+    provider: &str,
+    name: &str,
+    since: DateTime<Utc>,
+    connection: &dyn GenericConnection,
+) -> Result<bool> {
+    let rows = connection
+        .prepare_cached(
+            "INSERT INTO update_attempts (provider, name, attempted_at) VALUES ($1, lower($2), now()) \
+             ON CONFLICT (provider, name) DO UPDATE SET attempted_at = excluded.attempted_at \
+             WHERE update_attempts.attempted_at <= $3 RETURNING attempted_at",
+        )?
+        .query(&[&provider, &name, &since])?;
+
+    Ok(!rows.is_empty())
+}
+
+/// When the latest sync of a user started, whether or not it succeeded.
+pub fn find_last_sync_start(
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Option<DateTime<Utc>>> {
+    let rows = connection
+        .prepare_cached("SELECT max(started_at) as started_at FROM sync_runs WHERE user_id = $1")?
+        .query(&[&user_id])?;
+
+    Ok(rows.get(0).get("started_at"))
+}
+
+pub fn find_sync_status(
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<Option<models::SyncStatus>> {
+    let rows = connection
+        .prepare_cached(
+            "SELECT user_id, last_synced_at, last_sync_status, last_sync_duration \
+             FROM users WHERE user_id = $1",
+        )?
+        .query(&[&user_id])?;
+
+    Ok(rows.iter().next().map(|row| models::SyncStatus {
+        user_id: row.get("user_id"),
+        last_synced_at: row.get("last_synced_at"),
+        last_sync_status: row.get("last_sync_status"),
+        last_sync_duration: row.get("last_sync_duration"),
+    }))
+}
+
+/// Records how a sync went. Only successful syncs move `last_synced_at`, so it always says how
+/// fresh the stored list is.
+pub fn record_sync(
+    user_id: i32,
+    succeeded: bool,
+    status: &str,
+    duration: i32,
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached(
+            "UPDATE users SET last_synced_at = CASE WHEN $2 THEN now() ELSE last_synced_at END, \
+             last_sync_status = $3, last_sync_duration = $4 WHERE user_id = $1",
+        )?
+        .execute(&[&user_id, &succeeded, &status, &duration])
+}
+
+/// Notes that a user's list was looked at. Views within the same hour only count once, so reading a
+/// list rarely writes.
+pub fn record_view(user_id: i32, connection: &dyn GenericConnection) -> Result<u64> {
+    connection
+        .prepare_cached(
+            "UPDATE users SET last_viewed_at = now() WHERE user_id = $1 \
+             AND (last_viewed_at IS NULL OR last_viewed_at < now() - interval '1 hour')",
+        )?
+        .execute(&[&user_id])
+}
+
+/// Users with a provider to sync from who haven't been synced since `cutoff`, most recently viewed
+/// first, along with how many entries their stored list has.
+pub fn find_stale_users(
+    cutoff: DateTime<Utc>,
+    limit: i64,
+    connection: &dyn GenericConnection,
+) -> Result<Vec<(models::User, i64)>> {
+    let sql = format!(
+        "SELECT {}, (SELECT count(*) FROM lists as l \
+         WHERE l.user_id = u.user_id AND l.removed_at IS NULL) as entries \
+         FROM users as u WHERE u.provider <> 'local' AND u.external_id IS NOT NULL \
+         AND (u.last_synced_at IS NULL OR u.last_synced_at < $1) \
+         ORDER BY u.last_viewed_at DESC NULLS LAST, u.last_synced_at NULLS FIRST LIMIT $2",
+        columns::<models::User>("u")
+    );
+    let rows = connection.prepare_cached(&sql)?.query(&[&cutoff, &limit])?;
+
+    Ok(rows
+        .iter()
+        .map(|row| (models::User::from_row(&row), row.get("entries")))
+        .collect())
+}
+
+// Local ids count down from -1 so they never collide with AniList's.
+pub fn next_local_id(connection: &dyn GenericConnection) -> Result<i32> {
+    let rows = connection
+        .prepare_cached("SELECT nextval('local_user_ids')::integer")?
+        .query(&[])?;
+
+    Ok(rows.get(0).get(0))
+}
+
+pub fn save_alias(
+    provider: &str,
+    name: &str,
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<u64> {
+    connection
+        .prepare_cached(
+            "INSERT INTO user_aliases (provider, name, user_id, renamed_at) \
+             VALUES ($1, $2, $3, now()) ON CONFLICT (provider, name) \
+             DO UPDATE SET user_id = excluded.user_id, renamed_at = excluded.renamed_at",
+        )?
+        .execute(&[&provider, &name, &user_id])
+}
+
+// Names are unique per provider, so before a user takes a name any alias pointing elsewhere is
+// dropped, and a stored user still holding it must have been renamed since their last update. They
+// keep a placeholder name until they are updated again.
+pub fn release_name(
+    provider: &str,
+    name: &str,
+    user_id: i32,
+    connection: &dyn GenericConnection,
+) -> Result<()> {
+    connection
+        .prepare_cached("DELETE FROM user_aliases WHERE provider = $1 AND lower(name) = lower($2)")?
+        .execute(&[&provider, &name])?;
+    connection
+        .prepare_cached(
+            "UPDATE users SET name = name || '~' || user_id \
+             WHERE provider = $1 AND lower(name) = lower($2) AND user_id <> $3",
+        )?
+        .execute(&[&provider, &name, &user_id])?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{database, provider, repository, sync};
+use chrono::Utc;
+use dotenv::dotenv;
+use log::{error, info};
+use std::collections::HashMap;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub struct SchedulerOptions {
+    /// How often stale users are looked for. Zero turns the scheduler off.
+    pub interval: Duration,
+    /// How long after their last sync a user is refreshed.
+    pub stale_after: Duration,
+    /// How many rate limited requests background syncs may make per interval, as counted by
+    /// `ListProvider::requests_for`.
+    pub request_budget: usize,
+    /// How long entries removed from a user's provider are kept before being purged.
+    pub removed_grace_period: Duration,
+    /// How often removed entries past their grace period are purged. Zero turns purging off.
+    pub purge_interval: Duration,
+}
+
+impl SchedulerOptions {
+    pub fn from_env() -> SchedulerOptions {
+        dotenv().ok();
+
+        SchedulerOptions {
+            interval: Duration::from_secs(env_number("REFRESH_INTERVAL_SECONDS", 3600)),
+            stale_after: Duration::from_secs(env_number("REFRESH_STALE_AFTER_SECONDS", 86400)),
+            request_budget: env_number("REFRESH_REQUEST_BUDGET", 60) as usize,
+            removed_grace_period: Duration::from_secs(env_number(
+                "REMOVED_GRACE_PERIOD_SECONDS",
+                30 * 86400,
+            )),
+            purge_interval: Duration::from_secs(env_number("PURGE_INTERVAL_SECONDS", 3600)),
+        }
+    }
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Starts refreshing stale users in the background, so lists stay current without anyone pressing
+/// update, and purging removed entries once their grace period is over. Each runs on its own timer,
+/// so turning refreshes off still purges.
+pub fn start(options: SchedulerOptions) {
+    let options = Arc::new(options);
+
+    if options.purge_interval.as_secs() == 0 {
+        info!("Purging removed entries is turned off");
+    } else {
+        let options = Arc::clone(&options);
+        every(options.purge_interval, "purge", move || {
+            purge_removed(&options)
+        });
+    }
+
+    if options.interval.as_secs() == 0 || options.request_budget == 0 {
+        info!("Background refreshes are turned off");
+    } else {
+        let mut requested = HashMap::new();
+        let interval = options.interval;
+        every(interval, "refresh", move || {
+            refresh_stale(&options, &mut requested)
+        });
+    }
+}
+
+// Runs a task on a background thread once per interval. A failed run, such as when the database is
+// down, is retried next interval.
+fn every<F: FnMut() + Send + 'static>(interval: Duration, name: &'static str, mut task: F) {
+    thread::spawn(move || loop {
+        let start = Instant::now();
+        if panic::catch_unwind(AssertUnwindSafe(&mut task)).is_err() {
+            error!("background {} panicked", name);
+        }
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    });
+}
+
+fn purge_removed(options: &SchedulerOptions) {
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(options.removed_grace_period)
+            .unwrap_or_else(|_| chrono::Duration::days(30));
+    match repository::purge_removed(cutoff, &database::establish_connection()) {
+        Ok(0) => {}
+        Ok(purged) => info!("Purged {} removed entries", purged),
+        Err(error) => error!("error purging removed entries. Error: {}", error),
+    }
+}
+
+// Requests syncs for the stalest users within the request budget, spread across the interval so
+// AniList sees a steady trickle rather than a burst. `requested` remembers when each user was last
+// requested, since a failed sync leaves them stale and they shouldn't use up every run.
+fn refresh_stale(options: &SchedulerOptions, requested: &mut HashMap<i32, Instant>) {
+    requested.retain(|_, at| at.elapsed() < options.stale_after);
+
+    let connection = database::establish_connection();
+    let cutoff = Utc::now()
+        - chrono::Duration::from_std(options.stale_after)
+            .unwrap_or_else(|_| chrono::Duration::days(1));
+    let users = match repository::find_stale_users(
+        cutoff,
+        (options.request_budget + requested.len()) as i64,
+        &connection,
+    ) {
+        Ok(users) => users,
+        Err(error) => {
+            error!("error finding stale users. Error: {}", error);
+            return;
+        }
+    };
+    drop(connection);
+
+    let mut spent = 0;
+    for (user, entries) in users {
+        if requested.contains_key(&user.user_id) {
+            continue;
+        }
+        let (provider, external_id) = match (provider::for_name(&user.provider), user.external_id) {
+            (Some(provider), Some(external_id)) => (provider, external_id),
+            _ => continue,
+        };
+
+        let cost = provider.requests_for(entries as usize).max(1);
+        if spent + cost > options.request_budget {
+            break;
+        }
+        spent += cost;
+
+        info!("Refreshing stale user_id={}", user.user_id);
+        requested.insert(user.user_id, Instant::now());
+        sync::request(provider, user.user_id, external_id);
+        thread::sleep(options.interval * cost as u32 / options.request_budget as u32);
+    }
+}
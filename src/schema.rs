@@ -16,6 +16,12 @@ table! {
         native -> Nullable<Text>,
         romaji -> Nullable<Text>,
         english -> Nullable<Text>,
+        format -> Nullable<Text>,
+        episodes -> Nullable<Int4>,
+        duration -> Nullable<Int4>,
+        mal_id -> Nullable<Int4>,
+        description_text -> Nullable<Text>,
+        description_summary -> Nullable<Text>,
     }
 }
 
@@ -27,19 +33,108 @@ table! {
         start_day -> Nullable<Date>,
         end_day -> Nullable<Date>,
         score -> Nullable<Int2>,
+        status -> Nullable<Text>,
+        progress -> Nullable<Int4>,
+        removed_at -> Nullable<Timestamptz>,
     }
 }
 
+// Usernames are unique per provider regardless of case, and both they and aliases are looked up by
+// `(provider, lower(name))` indexes. The tables themselves are created by the files in migrations/.
 table! {
     users (user_id) {
         user_id -> Int4,
         name -> Text,
         avatar_s3 -> Text,
         avatar_anilist -> Text,
+        provider -> Text,
+        external_id -> Nullable<Int4>,
+        last_synced_at -> Nullable<Timestamptz>,
+        last_sync_status -> Nullable<Text>,
+        last_sync_duration -> Nullable<Int4>,
+        last_viewed_at -> Nullable<Timestamptz>,
+        token_hash -> Nullable<Bytea>,
     }
 }
 
+table! {
+    user_aliases (provider, name) {
+        provider -> Text,
+        name -> Text,
+        user_id -> Int4,
+        renamed_at -> Timestamptz,
+    }
+}
+
+table! {
+    update_attempts (provider, name) {
+        provider -> Text,
+        name -> Text,
+        attempted_at -> Timestamptz,
+    }
+}
+
+table! {
+    sync_runs (run_id) {
+        run_id -> Int4,
+        user_id -> Int4,
+        started_at -> Timestamptz,
+        finished_at -> Nullable<Timestamptz>,
+        added -> Int4,
+        updated -> Int4,
+        removed -> Int4,
+        images_uploaded -> Int4,
+        error -> Nullable<Text>,
+    }
+}
+
+table! {
+    list_changes (change_id) {
+        change_id -> Int4,
+        run_id -> Int4,
+        user_id -> Int4,
+        anime_id -> Int4,
+        changed_at -> Timestamptz,
+        change -> Text,
+        field -> Nullable<Text>,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+    }
+}
+
+table! {
+    list_history (history_id) {
+        history_id -> Int4,
+        user_id -> Int4,
+        anime_id -> Int4,
+        user_title -> Nullable<Text>,
+        start_day -> Nullable<Date>,
+        end_day -> Nullable<Date>,
+        score -> Nullable<Int2>,
+        status -> Nullable<Text>,
+        progress -> Nullable<Int4>,
+        valid_from -> Timestamptz,
+        valid_to -> Nullable<Timestamptz>,
+    }
+}
+
+joinable!(list_changes -> anime (anime_id));
+joinable!(list_changes -> sync_runs (run_id));
+joinable!(list_changes -> users (user_id));
+joinable!(list_history -> anime (anime_id));
+joinable!(list_history -> users (user_id));
 joinable!(lists -> anime (anime_id));
 joinable!(lists -> users (user_id));
+joinable!(sync_runs -> users (user_id));
+joinable!(user_aliases -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(anime, lists, users,);
+allow_tables_to_appear_in_same_query!(
+    anime,
+    list_changes,
+    list_history,
+    lists,
+    sync_runs,
+    update_attempts,
+    user_aliases,
+    users,
+);
@@ -0,0 +1,111 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::database;
+use crate::provider::ListProvider;
+use chrono::{Duration, Utc};
+use dotenv::dotenv;
+use log::error;
+use postgres::Connection;
+use std::collections::BTreeMap;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+// Every sync fetches the whole list and its images, so a user can only be synced this often.
+static DEFAULT_COOLDOWN_SECONDS: i64 = 300;
+
+// The users being synced by this server, and whether another sync has been asked for since theirs
+// started.
+static SYNCS: Mutex<BTreeMap<i32, bool>> = Mutex::new(BTreeMap::new());
+
+pub enum SyncRequest {
+    Started,
+    /// A sync was already running, so one more will run after it.
+    Queued,
+    /// A sync was already running with another queued after it, which will pick up this request.
+    AlreadyQueued,
+}
+
+/// Syncs a user's list in the background. Only one sync runs per user at a time, and requests made
+/// while one runs collapse into a single follow-up, since it will fetch the latest list anyway.
+pub fn request(provider: Box<dyn ListProvider + Send>, id: i32, external_id: i32) -> SyncRequest {
+    let mut syncs = lock_syncs();
+    match syncs.get_mut(&id) {
+        Some(true) => return SyncRequest::AlreadyQueued,
+        Some(queued) => {
+            *queued = true;
+            return SyncRequest::Queued;
+        }
+        None => {
+            syncs.insert(id, false);
+        }
+    }
+    drop(syncs);
+
+    thread::spawn(move || run(provider, id, external_id));
+    SyncRequest::Started
+}
+
+fn run(provider: Box<dyn ListProvider + Send>, id: i32, external_id: i32) {
+    loop {
+        // The user must be released even if a sync panics, or they could never be synced again.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            database::update_entries(provider.as_ref(), id, external_id)
+        }));
+        if result.is_err() {
+            error!("sync for user_id={} panicked", id);
+        }
+
+        let mut syncs = lock_syncs();
+        match syncs.get_mut(&id) {
+            Some(queued) if *queued => *queued = false,
+            _ => {
+                syncs.remove(&id);
+                return;
+            }
+        }
+    }
+}
+
+/// Claims an update of a user, or returns how many seconds to wait if one was asked for or started
+/// too recently. Failed syncs and unknown users count too, so this has to be claimed before
+/// anything is fetched from the provider.
+pub fn claim_update(username: &str, connection: &Connection) -> Result<(), i64> {
+    let cooldown = Duration::seconds(cooldown());
+    let now = Utc::now();
+    if let Some(last_update) = database::get_last_update(username, connection) {
+        let remaining = (last_update + cooldown - now).num_seconds();
+        if remaining > 0 {
+            return Err(remaining);
+        }
+    }
+
+    // Another request may have claimed it since the check above.
+    if database::claim_update(username, now - cooldown, connection) {
+        Ok(())
+    } else {
+        Err(cooldown.num_seconds())
+    }
+}
+
+fn cooldown() -> i64 {
+    dotenv().ok();
+
+    env::var("SYNC_COOLDOWN_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .unwrap_or(DEFAULT_COOLDOWN_SECONDS)
+}
+
+fn lock_syncs() -> MutexGuard<'static, BTreeMap<i32, bool>> {
+    SYNCS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
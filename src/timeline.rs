@@ -0,0 +1,325 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::html::escape;
+use crate::models;
+use chrono::{Datelike, Local, NaiveDate};
+use std::fmt::Write;
+
+static DEFAULT_WIDTH: u32 = 1200;
+static MIN_WIDTH: u32 = 300;
+static MAX_WIDTH: u32 = 4000;
+// The longest span drawn, counted back from the end of the range, so a far off `from` can't make
+// a tick for every month since year one.
+static MAX_YEARS: i32 = 100;
+static MARGIN: f64 = 10.0;
+static AXIS_HEIGHT: f64 = 40.0;
+static ROW_HEIGHT: f64 = 20.0;
+static BAR_HEIGHT: f64 = 16.0;
+static MONTH_LABELS: [&str; 12] = ["J", "F", "M", "A", "M", "J", "J", "A", "S", "O", "N", "D"];
+
+pub enum ColorBy {
+    Score,
+    Format,
+}
+
+impl ColorBy {
+    pub fn from_param(param: Option<String>) -> ColorBy {
+        match param.as_deref() {
+            Some("format") => ColorBy::Format,
+            _ => ColorBy::Score,
+        }
+    }
+}
+
+pub struct TimelineOptions {
+    pub width: Option<u32>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub color: ColorBy,
+}
+
+struct Bar<'a> {
+    title: &'a str,
+    start: NaiveDate,
+    end: NaiveDate,
+    fill: String,
+}
+
+/// Renders a user's list as a standalone SVG Gantt chart with one bar per entry that has a start
+/// date. Entries still being watched run up to today.
+pub fn render(list: &models::ResponseList, options: &TimelineOptions) -> String {
+    let today = Local::now().naive_local().date();
+
+    let mut bars: Vec<Bar> = list
+        .list
+        .iter()
+        .filter_map(|item| {
+            let start = item.start_day?;
+            let end = item.end_day.unwrap_or(today).max(start);
+            Some(Bar {
+                title: title(item),
+                start,
+                end,
+                fill: fill(item, &options.color),
+            })
+        })
+        .collect();
+    bars.sort_by(|a, b| a.start.cmp(&b.start).then(a.end.cmp(&b.end)));
+
+    let from = options
+        .from
+        .or_else(|| bars.iter().map(|b| b.start).min())
+        .unwrap_or(today);
+    let to = options
+        .to
+        .or_else(|| bars.iter().map(|b| b.end).max())
+        .unwrap_or(today)
+        .max(from);
+    let from = from.max(NaiveDate::from_ymd_opt(to.year() - MAX_YEARS, 1, 1).unwrap_or(from));
+    bars.retain(|b| b.end >= from && b.start <= to);
+
+    let width = options
+        .width
+        .unwrap_or(DEFAULT_WIDTH)
+        .max(MIN_WIDTH)
+        .min(MAX_WIDTH) as f64;
+    let height = AXIS_HEIGHT + ROW_HEIGHT * bars.len() as f64 + MARGIN;
+    // Pixels per day, with the last day of the range drawn in full.
+    let scale = (width - MARGIN * 2.0) / ((to - from).num_days() + 1) as f64;
+    let x = |date: NaiveDate| MARGIN + (date - from).num_days() as f64 * scale;
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+         viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"11\">",
+        w = width,
+        h = height
+    );
+    let _ = write!(
+        svg,
+        "<title>{}</title><rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>",
+        escape(&list.id)
+    );
+
+    // Month ticks along the top, with the year written at every January and at the first month.
+    let mut month = NaiveDate::from_ymd_opt(from.year(), from.month(), 1).unwrap();
+    let month_width = scale * 30.0;
+    while month <= to {
+        let tick = x(month.max(from));
+        let _ = write!(
+            svg,
+            "<line x1=\"{x:.1}\" y1=\"{y1}\" x2=\"{x:.1}\" y2=\"{y2}\" stroke=\"#dddddd\"/>",
+            x = tick,
+            y1 = AXIS_HEIGHT - 14.0,
+            y2 = height - MARGIN
+        );
+        if month.month() == 1 || month <= from {
+            let _ = write!(
+                svg,
+                "<text x=\"{:.1}\" y=\"14\" font-weight=\"bold\">{}</text>",
+                tick + 2.0,
+                month.year()
+            );
+        }
+        if month_width >= 12.0 {
+            let _ = write!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{}\" fill=\"#666666\">{}</text>",
+                tick + 2.0,
+                AXIS_HEIGHT - 4.0,
+                MONTH_LABELS[month.month0() as usize]
+            );
+        }
+        month = match next_month(month) {
+            Some(next) => next,
+            // The range runs to the last month chrono can represent.
+            None => break,
+        };
+    }
+
+    for (row, bar) in bars.iter().enumerate() {
+        let y = AXIS_HEIGHT + ROW_HEIGHT * row as f64;
+        let start = x(bar.start.max(from));
+        let bar_width = (x(bar.end.min(to)) - start + scale).max(2.0);
+        let _ = write!(
+            svg,
+            "<g><title>{title} ({start} - {end})</title>\
+             <rect x=\"{x:.1}\" y=\"{y:.1}\" width=\"{w:.1}\" height=\"{h}\" rx=\"3\" fill=\"{fill}\"/>",
+            title = escape(bar.title),
+            start = bar.start,
+            end = bar.end,
+            x = start,
+            y = y + (ROW_HEIGHT - BAR_HEIGHT) / 2.0,
+            w = bar_width,
+            h = BAR_HEIGHT,
+            fill = bar.fill
+        );
+        // Keep the label inside the chart by putting it to the left of bars that end near the edge.
+        let (label_x, anchor) = if start + bar_width + 4.0 > width * 0.7 {
+            (start - 4.0, "end")
+        } else {
+            (start + bar_width + 4.0, "start")
+        };
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"{}\">{}</text></g>",
+            label_x,
+            y + ROW_HEIGHT / 2.0 + 4.0,
+            anchor,
+            escape(bar.title)
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+pub fn title(item: &models::ResponseItem) -> &str {
+    &item.display_title
+}
+
+pub fn fill(item: &models::ResponseItem, color: &ColorBy) -> String {
+    match color {
+        // Scores are POINT_100, so map them from red at 0 to green at 100.
+        ColorBy::Score => match item.score {
+            Some(score) if score > 0 => format!("hsl({}, 65%, 50%)", score.min(100) as f64 * 1.2),
+            _ => "#9e9e9e".to_owned(),
+        },
+        ColorBy::Format => match item.format.as_deref() {
+            Some("TV") => "#3f51b5",
+            Some("TV_SHORT") => "#03a9f4",
+            Some("MOVIE") => "#e91e63",
+            Some("SPECIAL") => "#ff9800",
+            Some("OVA") => "#9c27b0",
+            Some("ONA") => "#009688",
+            Some("MUSIC") => "#8bc34a",
+            _ => "#9e9e9e",
+        }
+        .to_owned(),
+    }
+}
+
+fn next_month(date: NaiveDate) -> Option<NaiveDate> {
+    if date.month() == 12 {
+        NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, start: Option<NaiveDate>, end: Option<NaiveDate>) -> models::ResponseItem {
+        models::ResponseItem {
+            display_title: title.to_owned(),
+            user_title: None,
+            start_day: start,
+            end_day: end,
+            score: Some(80),
+            status: None,
+            progress: None,
+            average: None,
+            native: None,
+            romaji: None,
+            english: None,
+            format: None,
+            episodes: None,
+            duration: None,
+            description: None,
+            cover: String::new(),
+            id: 1,
+            removed_at: None,
+        }
+    }
+
+    fn list(items: Vec<models::ResponseItem>) -> models::ResponseList {
+        models::ResponseList {
+            id: "Foo".to_owned(),
+            avatar: String::new(),
+            last_synced_at: None,
+            list: items,
+        }
+    }
+
+    fn options(from: Option<NaiveDate>, to: Option<NaiveDate>) -> TimelineOptions {
+        TimelineOptions {
+            width: None,
+            from,
+            to,
+            color: ColorBy::Score,
+        }
+    }
+
+    fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn draws_only_entries_in_range() {
+        let list = list(vec![
+            item("Early", Some(day(2018, 1, 1)), Some(day(2018, 2, 1))),
+            item("Late", Some(day(2020, 1, 1)), Some(day(2020, 2, 1))),
+            item("Unstarted", None, None),
+        ]);
+        let svg = render(
+            &list,
+            &options(Some(day(2019, 1, 1)), Some(day(2021, 1, 1))),
+        );
+
+        assert!(svg.contains(">Late</text>"));
+        assert!(!svg.contains("Early"));
+        assert!(!svg.contains("Unstarted"));
+    }
+
+    #[test]
+    fn limits_the_span_to_max_years() {
+        let list = list(vec![item(
+            "Show",
+            Some(day(2020, 1, 1)),
+            Some(day(2020, 2, 1)),
+        )]);
+        let svg = render(&list, &options(Some(day(1, 1, 1)), Some(day(2020, 12, 31))));
+
+        let ticks = svg.matches("<line").count();
+        assert_eq!(ticks, (MAX_YEARS as usize + 1) * 12);
+        assert!(svg.contains(&format!(">{}</text>", 2020 - MAX_YEARS)));
+        assert!(!svg.contains(&format!(">{}</text>", 2020 - MAX_YEARS - 1)));
+    }
+
+    #[test]
+    fn keeps_to_after_from() {
+        let list = list(vec![item(
+            "Show",
+            Some(day(2020, 1, 1)),
+            Some(day(2020, 2, 1)),
+        )]);
+        let svg = render(
+            &list,
+            &options(Some(day(2020, 6, 1)), Some(day(2020, 1, 1))),
+        );
+
+        assert_eq!(svg.matches("<line").count(), 1);
+    }
+
+    #[test]
+    fn stops_at_the_last_representable_month() {
+        let list = list(vec![item(
+            "Show",
+            Some(day(2020, 1, 1)),
+            Some(day(2020, 2, 1)),
+        )]);
+        let svg = render(&list, &options(None, Some(NaiveDate::MAX)));
+
+        assert_eq!(svg.matches("<line").count(), (MAX_YEARS as usize + 1) * 12);
+        assert!(svg.contains(&format!(">{}</text>", NaiveDate::MAX.year())));
+    }
+}
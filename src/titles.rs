@@ -0,0 +1,228 @@
+/*
+ * Copyright (c) 2018, Tyler Bratton
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder};
+
+/// Which of an anime's titles to show. `User` is the one the list's owner chose on their provider.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TitleLanguage {
+    #[default]
+    User,
+    Romaji,
+    English,
+    Native,
+}
+
+impl TitleLanguage {
+    pub fn from_param(param: &str) -> Option<TitleLanguage> {
+        match param {
+            "user" => Some(TitleLanguage::User),
+            "romaji" => Some(TitleLanguage::Romaji),
+            "english" => Some(TitleLanguage::English),
+            "native" => Some(TitleLanguage::Native),
+            _ => None,
+        }
+    }
+
+    /// The title language for the most preferred of the languages in an `Accept-Language` header
+    /// that anime have titles in. Romanized Japanese is asked for as `ja-Latn`.
+    pub fn from_accept_language(header: &str) -> Option<TitleLanguage> {
+        let mut languages: Vec<(f32, String)> = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+                let quality = parts
+                    .filter_map(|part| part.trim().strip_prefix("q="))
+                    .find_map(|quality| quality.trim().parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, tag))
+            })
+            .filter(|(quality, _)| *quality > 0.0)
+            .collect();
+        // Stable, so languages of equal quality keep the order they were listed in.
+        languages.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        languages.iter().find_map(|(_, tag)| {
+            let mut subtags = tag.split('-');
+            match (subtags.next(), subtags.next()) {
+                (Some("ja"), Some("latn")) => Some(TitleLanguage::Romaji),
+                (Some("ja"), _) => Some(TitleLanguage::Native),
+                (Some("en"), _) => Some(TitleLanguage::English),
+                _ => None,
+            }
+        })
+    }
+
+    /// The title to show, falling back to the owner's title and then romaji, English and native
+    /// when the preferred one is missing.
+    pub fn pick(
+        self,
+        user_title: Option<&str>,
+        romaji: Option<&str>,
+        english: Option<&str>,
+        native: Option<&str>,
+    ) -> String {
+        let preferred = match self {
+            TitleLanguage::User => user_title,
+            TitleLanguage::Romaji => romaji,
+            TitleLanguage::English => english,
+            TitleLanguage::Native => native,
+        };
+        preferred
+            .or(user_title)
+            .or(romaji)
+            .or(english)
+            .or(native)
+            .unwrap_or("")
+            .to_owned()
+    }
+}
+
+/// The title language a request asks for through its `Accept-Language` header, if any. A `title`
+/// query parameter takes precedence over it.
+pub struct AcceptLanguage(pub Option<TitleLanguage>);
+
+impl AcceptLanguage {
+    /// The title language to show, failing when the `title` parameter isn't one of them.
+    pub fn with_param(self, title: Option<String>) -> Result<TitleLanguage, String> {
+        match title {
+            Some(title) => TitleLanguage::from_param(&title).ok_or_else(|| {
+                format!(
+                    "Unknown title {}, expected user, romaji, english or native",
+                    title
+                )
+            }),
+            None => Ok(self.0.unwrap_or_default()),
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AcceptLanguage {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> Outcome<AcceptLanguage, ()> {
+        Outcome::Success(AcceptLanguage(
+            request
+                .headers()
+                .get_one("Accept-Language")
+                .and_then(TitleLanguage::from_accept_language),
+        ))
+    }
+}
+
+/// A response whose titles depend on the request's `Accept-Language` header, which it says so caches
+/// keep a copy per language.
+pub struct Localized<R>(pub R);
+
+impl<'r, R: Responder<'r>> Responder<'r> for Localized<R> {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut response = self.0.respond_to(request)?;
+        response.adjoin_raw_header("Vary", "Accept-Language");
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pick_all(language: TitleLanguage) -> String {
+        language.pick(
+            Some("Mine"),
+            Some("Romaji"),
+            Some("English"),
+            Some("Native"),
+        )
+    }
+
+    #[test]
+    fn reads_title_params() {
+        assert_eq!(
+            TitleLanguage::from_param("romaji"),
+            Some(TitleLanguage::Romaji)
+        );
+        assert_eq!(TitleLanguage::from_param("Romaji"), None);
+        assert_eq!(TitleLanguage::from_param("french"), None);
+    }
+
+    #[test]
+    fn picks_the_most_preferred_language_with_titles() {
+        assert_eq!(
+            TitleLanguage::from_accept_language("fr-FR, en-US;q=0.8, ja;q=0.9"),
+            Some(TitleLanguage::Native)
+        );
+        assert_eq!(
+            TitleLanguage::from_accept_language("ja-Latn, ja;q=0.5"),
+            Some(TitleLanguage::Romaji)
+        );
+        assert_eq!(
+            TitleLanguage::from_accept_language("EN-gb"),
+            Some(TitleLanguage::English)
+        );
+    }
+
+    #[test]
+    fn keeps_listed_order_for_equal_quality() {
+        assert_eq!(
+            TitleLanguage::from_accept_language("en, ja"),
+            Some(TitleLanguage::English)
+        );
+        assert_eq!(
+            TitleLanguage::from_accept_language("ja;q=0.7, en;q=0.7"),
+            Some(TitleLanguage::Native)
+        );
+    }
+
+    #[test]
+    fn ignores_refused_and_unknown_languages() {
+        assert_eq!(
+            TitleLanguage::from_accept_language("ja;q=0, en;q=0.1"),
+            Some(TitleLanguage::English)
+        );
+        assert_eq!(TitleLanguage::from_accept_language("fr, de, *"), None);
+        assert_eq!(TitleLanguage::from_accept_language(""), None);
+    }
+
+    #[test]
+    fn picks_the_preferred_title() {
+        assert_eq!(pick_all(TitleLanguage::User), "Mine");
+        assert_eq!(pick_all(TitleLanguage::Romaji), "Romaji");
+        assert_eq!(pick_all(TitleLanguage::English), "English");
+        assert_eq!(pick_all(TitleLanguage::Native), "Native");
+    }
+
+    #[test]
+    fn falls_back_when_the_preferred_title_is_missing() {
+        assert_eq!(
+            TitleLanguage::English.pick(Some("Mine"), Some("Romaji"), None, Some("Native")),
+            "Mine"
+        );
+        assert_eq!(
+            TitleLanguage::English.pick(None, None, None, Some("Native")),
+            "Native"
+        );
+        assert_eq!(TitleLanguage::Native.pick(None, None, None, None), "");
+    }
+
+    #[test]
+    fn title_params_override_the_header() {
+        let header = || AcceptLanguage(Some(TitleLanguage::Native));
+        assert_eq!(header().with_param(None), Ok(TitleLanguage::Native));
+        assert_eq!(
+            header().with_param(Some("english".to_owned())),
+            Ok(TitleLanguage::English)
+        );
+        assert_eq!(
+            AcceptLanguage(None).with_param(None),
+            Ok(TitleLanguage::User)
+        );
+        assert!(header().with_param(Some("french".to_owned())).is_err());
+    }
+}